@@ -0,0 +1,1033 @@
+// assembler.rs - Turns parsed statements into an encoded btern executable.
+//
+// Assembly runs in two passes: pass 1 walks the source assigning a tryte address
+// to every label, and pass 2 builds the instructions and data, resolving label
+// operands against the completed symbol table.
+//
+// Code and data are placed in three sections, selected with `.text` (the
+// default), `.data` and `.bss`. Each section has its own location counter;
+// after pass 1 they are laid out one after another, each starting on a Word
+// boundary: .text at address 0, then .data, then .bss.
+//
+// With `-c`, basm writes a relocatable object instead: every section starts at
+// address 0, and each reference to a label's address (as well as PC-relative
+// references into another section or to an `.extern` symbol) becomes a
+// relocation for bld to resolve.
+//
+// Problems are collected as diagnostics (see diagnostic.rs) rather than stopping
+// at the first: a statement that fails is reported and skipped, and both passes
+// carry on so that one run shows every error in the file.
+//
+// Numeric operands are expressions (see expr.rs). `.equ` constants are collected
+// before either pass, so they can be used anywhere in the file, including by
+// pseudo-instructions whose expansion depends on the value.
+
+use btern_core::exe::{self, Executable, Permissions, Segment};
+use btern_core::object::{Object, ObjectSection, ObjectSymbol, Relocation, RelocationKind};
+use btern_core::{
+    encode_instruction, i64_to_tryte, i64_to_word, word_to_i64, word_to_trytes, Format, Instruction, Opcode, Trit, Tryte, Word,
+    IMM_MAX, IMM_MIN, TRYTES_PER_WORD, TRYTE_MAX, WORD_MAX,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::{has_errors, sort_diagnostics, Diagnostic};
+use crate::expr::{evaluate, symbol_names, Address, Base, Value};
+use crate::listing::{Emission, ListedSymbol, Listing};
+use crate::parser::{is_symbol_name, parse_register, parse_source, parse_string_literal, Item, Statement};
+use crate::pseudo::{expand_pseudo_instructions, is_pseudo};
+
+/// The sections a program is assembled into, in memory order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Section {
+    /// Instructions and read-only data.
+    Text,
+    /// Initialized, writable data.
+    Data,
+    /// Zero-initialized, writable data; occupies no space in the output file.
+    Bss,
+}
+
+impl Section {
+    const ALL: [Section; 3] = [Section::Text, Section::Data, Section::Bss];
+
+    /// Returns the section selected by a `.text`, `.data` or `.bss` directive.
+    fn from_directive(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            ".text" => Some(Section::Text),
+            ".data" => Some(Section::Data),
+            ".bss" => Some(Section::Bss),
+            _ => None,
+        }
+    }
+
+    /// Returns the segment name used in the executable.
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Data => ".data",
+            Section::Bss => ".bss",
+        }
+    }
+
+    fn permissions(self) -> Permissions {
+        match self {
+            Section::Text => Permissions::RX,
+            Section::Data | Section::Bss => Permissions::RW,
+        }
+    }
+}
+
+/// Per-section values, indexed by Section.
+#[derive(Debug, Default, Clone)]
+struct PerSection<T>([T; 3]);
+
+impl<T> std::ops::Index<Section> for PerSection<T> {
+    type Output = T;
+    fn index(&self, section: Section) -> &T {
+        &self.0[section as usize]
+    }
+}
+
+impl<T> std::ops::IndexMut<Section> for PerSection<T> {
+    fn index_mut(&mut self, section: Section) -> &mut T {
+        &mut self.0[section as usize]
+    }
+}
+
+/// A label defined in the source.
+#[derive(Debug, Copy, Clone)]
+pub struct Symbol {
+    /// Tryte address the label refers to (its section offset in an object).
+    pub address: i64,
+    /// Section the label is defined in.
+    pub section: Section,
+    /// Source line of the definition, used to report duplicates.
+    pub line: usize,
+}
+
+/// Maps label names to their definitions.
+pub type SymbolTable = HashMap<String, Symbol>;
+
+/// A constant defined with `.equ`.
+#[derive(Debug, Copy, Clone)]
+pub struct Constant {
+    pub value: Word,
+    /// Source line of the definition, used to report duplicates.
+    pub line: usize,
+}
+
+/// Maps `.equ` names to their values.
+pub type Constants = HashMap<String, Constant>;
+
+/// Supplies the value of each symbol in an expression.
+type Lookup<'a> = dyn Fn(&str) -> Result<Value, String> + 'a;
+
+/// Assembler directives that emit data or move the location counter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Directive {
+    /// `.word v, ...` - one 27-trit Word (3 trytes) per value.
+    Word,
+    /// `.tryte v, ...` - one 9-trit Tryte per value.
+    Tryte,
+    /// `.string "text"` - one Tryte per character, followed by a zero Tryte.
+    String,
+    /// `.zero N` - N zero Trytes.
+    Zero,
+    /// `.org ADDR` - moves the location counter forward to ADDR, relative to the section start.
+    Org,
+    /// `.align [N]` - pads with zero Trytes to a multiple of N (default: one Word).
+    Align,
+    /// `.global name, ...` - makes labels visible to other objects.
+    Global,
+    /// `.extern name, ...` - declares labels defined in another object.
+    Extern,
+    /// `.equ name, value` - defines a named constant.
+    Equ,
+}
+
+impl Directive {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            ".word" => Some(Directive::Word),
+            ".tryte" => Some(Directive::Tryte),
+            ".string" => Some(Directive::String),
+            ".zero" => Some(Directive::Zero),
+            ".org" => Some(Directive::Org),
+            ".align" => Some(Directive::Align),
+            ".global" => Some(Directive::Global),
+            ".extern" => Some(Directive::Extern),
+            ".equ" => Some(Directive::Equ),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if `name` is an instruction, pseudo-instruction or directive, which a macro may not shadow.
+pub fn is_reserved_mnemonic(name: &str) -> bool {
+    Opcode::from_mnemonic(name).is_some()
+        || is_pseudo(name)
+        || Directive::from_name(name).is_some()
+        || Section::from_directive(name).is_some()
+}
+
+/// Label that marks the program's entry point; without it execution starts at address 0.
+pub const ENTRY_SYMBOL: &str = "_start";
+
+/// The result of pass 1: where every label and section lives.
+struct Layout {
+    symbols: SymbolTable,
+    constants: Constants,
+    /// Names declared `.global`.
+    globals: HashSet<String>,
+    /// Names declared `.extern`.
+    externs: HashSet<String>,
+    bases: PerSection<i64>,
+    sizes: PerSection<i64>,
+}
+
+impl Layout {
+    /// Returns the value of symbol `name` in an expression: a constant, or a label's
+    /// address (its section offset in an object, or 0 for an external symbol).
+    fn lookup(&self, name: &str, relocatable: bool) -> Result<Value, String> {
+        if let Some(constant) = self.constants.get(name) {
+            return Ok(Value::constant(constant.value));
+        }
+        let (address, base) = match self.symbols.get(name) {
+            Some(symbol) if relocatable => (symbol.address, Base::Section(symbol.section)),
+            Some(symbol) => (symbol.address, Base::Absolute),
+            None if self.externs.contains(name) && relocatable => (0, Base::External),
+            None if self.externs.contains(name) => {
+                return Err(format!(
+                    "label '{}' is declared .extern; assemble with -c and link with bld",
+                    name
+                ))
+            }
+            None => return Err(format!("undefined label '{}'", name)),
+        };
+        Ok(Value {
+            word: i64_to_word(address),
+            address: Some(Address {
+                symbol: name.to_string(),
+                base,
+            }),
+        })
+    }
+}
+
+/// A label reference that can only be resolved by the linker.
+struct PendingRelocation {
+    section: Section,
+    /// Tryte offset of the patched Word or Tryte within the section.
+    offset: i64,
+    kind: RelocationKind,
+    symbol: String,
+    addend: i64,
+}
+
+/// Resolves label operands during pass 2. When assembling a relocatable object,
+/// references that depend on the final layout are recorded as relocations instead.
+struct Resolver<'a> {
+    layout: &'a Layout,
+    /// Section of the statement being assembled.
+    section: Section,
+    relocatable: bool,
+    relocations: Vec<PendingRelocation>,
+}
+
+impl Resolver<'_> {
+    /// Resolves `value`, used by the Word or Tryte at `address`. An address becomes
+    /// its final value, or for `Rel12` its distance from `address`; a plain number
+    /// is used as is. In an object, an address that depends on the final layout
+    /// resolves to 0 and a relocation is recorded.
+    fn resolve(&mut self, value: Value, kind: RelocationKind, address: i64) -> i64 {
+        let number = value.to_i64();
+        let Some(target) = value.address else {
+            return number;
+        };
+
+        match target.base {
+            Base::Absolute if kind == RelocationKind::Rel12 => number - address,
+            Base::Absolute => number,
+            // A PC-relative reference within one section does not depend on where it is loaded.
+            Base::Section(section) if kind == RelocationKind::Rel12 && section == self.section => number - address,
+            Base::Section(_) | Base::External => {
+                let offset = self.layout.symbols.get(&target.symbol).map_or(0, |symbol| symbol.address);
+                self.relocations.push(PendingRelocation {
+                    section: self.section,
+                    offset: address,
+                    kind,
+                    symbol: target.symbol,
+                    addend: number - offset,
+                });
+                0
+            }
+        }
+    }
+}
+
+/// Assembles a complete source file into an executable and its listing. Errors and
+/// warnings are appended to `diagnostics`; there is no executable if any of them is an error.
+pub fn assemble(source: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<(Executable, Listing)> {
+    let Assembly {
+        layout,
+        mut images,
+        listing,
+        ..
+    } = assemble_sections(source, false, diagnostics)?;
+
+    let segments = Section::ALL
+        .into_iter()
+        .filter(|&section| layout.sizes[section] > 0)
+        .map(|section| Segment {
+            name: section.name().to_string(),
+            address: layout.bases[section],
+            size: layout.sizes[section] as usize,
+            permissions: section.permissions(),
+            data: std::mem::take(&mut images[section]),
+        })
+        .collect();
+
+    let mut exe_symbols: Vec<exe::Symbol> = layout
+        .symbols
+        .iter()
+        .map(|(name, symbol)| exe::Symbol {
+            name: name.clone(),
+            address: symbol.address,
+        })
+        .collect();
+    exe_symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+
+    let exe = Executable {
+        entry: layout.symbols.get(ENTRY_SYMBOL).map_or(0, |symbol| symbol.address),
+        segments,
+        symbols: exe_symbols,
+    };
+    Some((exe, listing))
+}
+
+/// Assembles a complete source file into a relocatable object for bld and its
+/// listing, reporting to `diagnostics` like assemble.
+pub fn assemble_object(source: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<(Object, Listing)> {
+    let Assembly {
+        layout,
+        mut images,
+        relocations: pending,
+        listing,
+    } = assemble_sections(source, true, diagnostics)?;
+
+    let mut object = Object::default();
+    let mut section_index: PerSection<usize> = PerSection::default();
+    for section in Section::ALL {
+        if layout.sizes[section] > 0 {
+            section_index[section] = object.sections.len();
+            object.sections.push(ObjectSection {
+                name: section.name().to_string(),
+                size: layout.sizes[section] as usize,
+                permissions: section.permissions(),
+                data: std::mem::take(&mut images[section]),
+            });
+        }
+    }
+
+    // Defined labels first, in address order, then the external references.
+    let mut defined: Vec<(&String, &Symbol)> = layout.symbols.iter().collect();
+    defined.sort_by(|a, b| (a.1.section as usize, a.1.address, a.0).cmp(&(b.1.section as usize, b.1.address, b.0)));
+    for (name, symbol) in defined {
+        object.symbols.push(ObjectSymbol {
+            name: name.clone(),
+            section: Some(section_index[symbol.section]),
+            offset: symbol.address,
+            global: layout.globals.contains(name),
+        });
+    }
+    let mut externs: Vec<&String> = layout.externs.iter().collect();
+    externs.sort();
+    for name in externs {
+        object.symbols.push(ObjectSymbol {
+            name: name.clone(),
+            section: None,
+            offset: 0,
+            global: true,
+        });
+    }
+
+    let symbol_index: HashMap<&str, usize> = object
+        .symbols
+        .iter()
+        .enumerate()
+        .map(|(index, symbol)| (symbol.name.as_str(), index))
+        .collect();
+    object.relocations = pending
+        .iter()
+        .map(|reloc| Relocation {
+            section: section_index[reloc.section],
+            offset: reloc.offset as usize,
+            kind: reloc.kind,
+            symbol: symbol_index[reloc.symbol.as_str()],
+            addend: reloc.addend,
+        })
+        .collect();
+
+    Some((object, listing))
+}
+
+/// The result of both passes.
+struct Assembly {
+    layout: Layout,
+    /// Each section's contents; empty for .bss.
+    images: PerSection<Vec<Tryte>>,
+    /// References left for the linker; always empty for an executable.
+    relocations: Vec<PendingRelocation>,
+    listing: Listing,
+}
+
+/// Runs both passes over `source`, appending problems to `diagnostics` in source
+/// order. Returns None if there were errors.
+fn assemble_sections(source: &str, relocatable: bool, diagnostics: &mut Vec<Diagnostic>) -> Option<Assembly> {
+    let start = diagnostics.len();
+    let mut found = Vec::new();
+    let items = parse_source(source, &mut found);
+    let constants = collect_constants(&items, &mut found);
+    // Uses are collected before pseudo-instructions replace constants with their values.
+    let uses = symbol_uses(&items);
+    let items = expand_pseudo_instructions(items, &constants, &mut found);
+    let layout = collect_symbols(&items, constants, relocatable, &mut found);
+
+    let mut images: PerSection<Vec<Tryte>> = PerSection::default();
+    let mut offsets: PerSection<i64> = PerSection::default();
+    let mut resolver = Resolver {
+        layout: &layout,
+        section: Section::Text,
+        relocatable,
+        relocations: Vec::new(),
+    };
+    let mut listing = Listing::default();
+    for (name, line) in uses {
+        if layout.lookup(&name, true).is_ok() {
+            listing.reference(&name, line);
+        }
+    }
+    // Line of a HALT that no label has followed yet: code after it cannot be reached.
+    let mut halted: Option<usize> = None;
+    for item in &items {
+        let stmt = match item {
+            Item::Label(_) => {
+                halted = None;
+                continue;
+            }
+            Item::Statement(stmt) => stmt,
+        };
+        if let Some(selected) = Section::from_directive(&stmt.mnemonic) {
+            resolver.section = selected;
+            halted = None;
+            continue;
+        }
+        if Directive::from_name(&stmt.mnemonic).is_none() {
+            if let Some(line) = halted.take() {
+                found.push(stmt.warning(format!(
+                    "unreachable code: follows HALT on line {} with no label in between",
+                    line
+                )));
+            }
+            if Opcode::from_mnemonic(&stmt.mnemonic) == Some(Opcode::HALT) {
+                halted = Some(stmt.line());
+            }
+        }
+
+        let section = resolver.section;
+        let address = offsets[section];
+        let lookup = |name: &str| layout.lookup(name, relocatable);
+        let next = match next_address(stmt, address, &lookup) {
+            Ok(next) => next,
+            Err(e) => {
+                found.push(e);
+                continue;
+            }
+        };
+        let base = layout.bases[section];
+        let image = &mut images[section];
+        match emit_statement(stmt, base, address, next, &mut resolver, image, &mut found) {
+            Ok(()) if next > address => listing.emissions.push(Emission {
+                line: stmt.line(),
+                address: base + address,
+                trytes: if emits_nothing(stmt) { Vec::new() } else { image[address as usize..next as usize].to_vec() },
+                reserved: if emits_nothing(stmt) { next - address } else { 0 },
+                instruction: Directive::from_name(&stmt.mnemonic).is_none(),
+            }),
+            Ok(()) => {}
+            Err(e) => found.push(e),
+        }
+        offsets[section] = next;
+    }
+
+    sort_diagnostics(&mut found);
+    diagnostics.extend(found);
+    if has_errors(&diagnostics[start..]) {
+        return None;
+    }
+
+    // .bss is all zeros, so none of it is stored.
+    images[Section::Bss].clear();
+    let relocations = resolver.relocations;
+    listing.symbols = listed_symbols(&layout);
+    Some(Assembly {
+        layout,
+        images,
+        relocations,
+        listing,
+    })
+}
+
+/// Returns each name used in an operand expression, with the line using it.
+fn symbol_uses(items: &[Item]) -> Vec<(String, usize)> {
+    let mut uses = Vec::new();
+    for item in items {
+        let Item::Statement(stmt) = item else { continue };
+        // The name an .equ defines is not a use of it.
+        let skip = usize::from(Directive::from_name(&stmt.mnemonic) == Some(Directive::Equ));
+        for operand in stmt.operands.iter().skip(skip) {
+            // Registers and strings are not expressions, and name no symbols.
+            let names = symbol_names(operand).unwrap_or_default();
+            uses.extend(names.into_iter().map(|name| (name, stmt.line())));
+        }
+    }
+    uses
+}
+
+/// Returns every label, constant and external symbol for the listing.
+fn listed_symbols(layout: &Layout) -> Vec<ListedSymbol> {
+    let labels = layout.symbols.iter().map(|(name, symbol)| ListedSymbol {
+        name: name.clone(),
+        value: symbol.address,
+        kind: symbol.section.name(),
+        line: Some(symbol.line),
+    });
+    let constants = layout.constants.iter().map(|(name, constant)| ListedSymbol {
+        name: name.clone(),
+        value: word_to_i64(&constant.value),
+        kind: ".equ",
+        line: Some(constant.line),
+    });
+    let externs = layout.externs.iter().map(|name| ListedSymbol {
+        name: name.clone(),
+        value: 0,
+        kind: "extern",
+        line: None,
+    });
+    labels.chain(constants).chain(externs).collect()
+}
+
+/// Evaluates every `.equ` in source order. A constant's value may use literals and
+/// constants defined above it, but not labels, whose addresses are not known yet.
+/// A constant whose value is in error is recorded as 0, so its uses are not reported too.
+fn collect_constants(items: &[Item], diagnostics: &mut Vec<Diagnostic>) -> Constants {
+    let mut constants = Constants::new();
+    for item in items {
+        let Item::Statement(stmt) = item else { continue };
+        if Directive::from_name(&stmt.mnemonic) != Some(Directive::Equ) {
+            continue;
+        }
+        if let Err(e) = expect_operands(stmt, 2) {
+            diagnostics.push(stmt.error(e));
+            continue;
+        }
+
+        let name = &stmt.operands[0];
+        if !is_symbol_name(name) {
+            diagnostics.push(stmt.operand_error(0, format!("invalid constant name '{}' in .equ", name)));
+            continue;
+        }
+        if let Some(previous) = constants.get(name) {
+            diagnostics.push(stmt.operand_error(
+                0,
+                format!("duplicate constant '{}' (first defined on line {})", name, previous.line),
+            ));
+            continue;
+        }
+        let lookup = |symbol: &str| match constants.get(symbol) {
+            Some(constant) => Ok(Value::constant(constant.value)),
+            None => Err(format!(
+                "'{}' is not a constant defined earlier with .equ; .equ values cannot use labels",
+                symbol
+            )),
+        };
+        let value = evaluate(&stmt.operands[1], &lookup).unwrap_or_else(|e| {
+            diagnostics.push(stmt.operand_error(1, e));
+            Value::constant(i64_to_word(0))
+        });
+        constants.insert(
+            name.clone(),
+            Constant {
+                value: value.word,
+                line: stmt.line(),
+            },
+        );
+    }
+    constants
+}
+
+/// Pass 1: assigns a tryte address to every label and lays out the sections.
+/// In an object every section starts at 0, so labels hold section offsets.
+/// Statements in error are reported and take no space.
+fn collect_symbols(
+    items: &[Item],
+    constants: Constants,
+    relocatable: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Layout {
+    // Labels are first recorded as offsets into their section, then relocated
+    // once the size of every section is known.
+    let mut symbols = SymbolTable::new();
+    // Declared names, with the declaring statement and operand index.
+    let mut globals: Vec<(String, &Statement, usize)> = Vec::new();
+    let mut externs: Vec<(String, &Statement, usize)> = Vec::new();
+    let mut sizes: PerSection<i64> = PerSection::default();
+    let mut section = Section::Text;
+
+    for item in items {
+        match item {
+            Item::Label(label) => {
+                if let Some(previous) = symbols.get(&label.name) {
+                    diagnostics.push(label.error(format!(
+                        "duplicate label '{}' (first defined on line {})",
+                        label.name, previous.line
+                    )));
+                    continue;
+                }
+                if let Some(constant) = constants.get(&label.name) {
+                    diagnostics.push(label.error(format!(
+                        "label '{}' conflicts with the .equ constant on line {}",
+                        label.name, constant.line
+                    )));
+                    continue;
+                }
+                symbols.insert(
+                    label.name.clone(),
+                    Symbol {
+                        address: sizes[section],
+                        section,
+                        line: label.span.line,
+                    },
+                );
+            }
+            Item::Statement(stmt) => {
+                if let Some(selected) = Section::from_directive(&stmt.mnemonic) {
+                    if let Err(e) = expect_operands(stmt, 0) {
+                        diagnostics.push(stmt.error(e));
+                    }
+                    section = selected;
+                    continue;
+                }
+                if section == Section::Bss && !emits_nothing(stmt) {
+                    diagnostics.push(stmt.error(format!(
+                        ".bss can only reserve space (.zero, .org, .align), found '{}'",
+                        stmt.mnemonic
+                    )));
+                    continue;
+                }
+                // Only labels defined so far have an address; they are still section offsets.
+                let lookup = |name: &str| match (constants.get(name), symbols.get(name)) {
+                    (Some(constant), _) => Ok(Value::constant(constant.value)),
+                    (None, Some(symbol)) => Ok(Value {
+                        word: i64_to_word(symbol.address),
+                        address: Some(Address {
+                            symbol: name.to_string(),
+                            base: Base::Section(symbol.section),
+                        }),
+                    }),
+                    (None, None) => Err(format!("'{}' must be defined before it is used here", name)),
+                };
+                match next_address(stmt, sizes[section], &lookup) {
+                    Ok(next) => sizes[section] = next,
+                    Err(e) => {
+                        diagnostics.push(e);
+                        continue;
+                    }
+                }
+
+                let declared = stmt.operands.iter().enumerate().map(|(i, name)| (name.clone(), stmt, i));
+                match Directive::from_name(&stmt.mnemonic) {
+                    Some(Directive::Global) => globals.extend(declared),
+                    Some(Directive::Extern) => externs.extend(declared),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for (name, stmt, index) in &globals {
+        if !symbols.contains_key(name) {
+            diagnostics.push(stmt.operand_error(*index, format!(".global label '{}' is never defined", name)));
+        }
+    }
+    for (name, stmt, index) in &externs {
+        if let Some(symbol) = symbols.get(name) {
+            diagnostics.push(stmt.operand_error(
+                *index,
+                format!("label '{}' is declared .extern but defined on line {}", name, symbol.line),
+            ));
+        }
+    }
+
+    let word = TRYTES_PER_WORD as i64;
+    let mut bases: PerSection<i64> = PerSection::default();
+    if !relocatable {
+        let mut end = 0;
+        for section in Section::ALL {
+            bases[section] = (end + word - 1) / word * word;
+            end = bases[section] + sizes[section];
+        }
+        for symbol in symbols.values_mut() {
+            symbol.address += bases[symbol.section];
+        }
+    }
+
+    Layout {
+        symbols,
+        constants,
+        globals: globals.into_iter().map(|(name, ..)| name).collect(),
+        externs: externs.into_iter().map(|(name, ..)| name).collect(),
+        bases,
+        sizes,
+    }
+}
+
+/// Returns true if `stmt` emits no data: it only moves the location counter or declares symbols.
+fn emits_nothing(stmt: &Statement) -> bool {
+    matches!(
+        Directive::from_name(&stmt.mnemonic),
+        Some(
+            Directive::Zero
+                | Directive::Org
+                | Directive::Align
+                | Directive::Global
+                | Directive::Extern
+                | Directive::Equ
+        )
+    )
+}
+
+/// Returns the location counter after `stmt`, given its value before.
+/// Both passes use this so that label addresses and emitted data always agree;
+/// `lookup` supplies the symbols a `.zero`, `.org` or `.align` operand may use.
+fn next_address(stmt: &Statement, address: i64, lookup: &Lookup) -> Result<i64, Diagnostic> {
+    let Some(directive) = Directive::from_name(&stmt.mnemonic) else {
+        return Ok(address + TRYTES_PER_WORD as i64);
+    };
+    let ops = &stmt.operands;
+    let constant = |index: usize| evaluate_constant(&ops[index], lookup).map_err(|e| stmt.operand_error(index, e));
+
+    match directive {
+        Directive::Word | Directive::Tryte => {
+            if ops.is_empty() {
+                return Err(stmt.error(format!("{} expects at least one value", stmt.mnemonic)));
+            }
+            let width = if directive == Directive::Word { TRYTES_PER_WORD as i64 } else { 1 };
+            Ok(address + width * ops.len() as i64)
+        }
+        Directive::String => {
+            expect_operands(stmt, 1).map_err(|e| stmt.error(e))?;
+            // One tryte per character plus the zero terminator.
+            let text = parse_string_literal(&ops[0]).map_err(|e| stmt.operand_error(0, e))?;
+            Ok(address + text.chars().count() as i64 + 1)
+        }
+        Directive::Zero => {
+            expect_operands(stmt, 1).map_err(|e| stmt.error(e))?;
+            let count = constant(0)?;
+            if count < 0 {
+                return Err(stmt.operand_error(0, format!(".zero count must not be negative, found {}", count)));
+            }
+            Ok(address + count)
+        }
+        Directive::Org => {
+            expect_operands(stmt, 1).map_err(|e| stmt.error(e))?;
+            let target = constant(0)?;
+            if target < address {
+                return Err(stmt.operand_error(
+                    0,
+                    format!(
+                        ".org {} would move the location counter backwards (currently {})",
+                        target, address
+                    ),
+                ));
+            }
+            Ok(target)
+        }
+        Directive::Align => {
+            if ops.len() > 1 {
+                return Err(stmt.error(".align expects at most one operand"));
+            }
+            let alignment = if ops.is_empty() { TRYTES_PER_WORD as i64 } else { constant(0)? };
+            if alignment <= 0 {
+                return Err(stmt.operand_error(0, format!(".align boundary must be positive, found {}", alignment)));
+            }
+            Ok((address + alignment - 1) / alignment * alignment)
+        }
+        Directive::Global | Directive::Extern => {
+            if ops.is_empty() {
+                return Err(stmt.error(format!("{} expects at least one label", stmt.mnemonic)));
+            }
+            if let Some(index) = ops.iter().position(|name| !is_symbol_name(name)) {
+                return Err(stmt.operand_error(
+                    index,
+                    format!("invalid label name '{}' in {}", ops[index], stmt.mnemonic),
+                ));
+            }
+            Ok(address)
+        }
+        // The constant was evaluated before pass 1.
+        Directive::Equ => {
+            expect_operands(stmt, 2).map_err(|e| stmt.error(e))?;
+            Ok(address)
+        }
+    }
+}
+
+/// Pass 2: writes the trytes for `stmt` into the section `image`, from offset `address`
+/// up to `next`. `base` is the tryte address the section is loaded at. Warnings about
+/// the statement are appended to `diagnostics`.
+fn emit_statement(
+    stmt: &Statement,
+    base: i64,
+    address: i64,
+    next: i64,
+    resolver: &mut Resolver,
+    image: &mut Vec<Tryte>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<(), Diagnostic> {
+    let mut trytes: Vec<Tryte> = Vec::new();
+
+    match Directive::from_name(&stmt.mnemonic) {
+        None => {
+            let inst = build_instruction(stmt, base + address, resolver)?;
+            lint_instruction(stmt, &inst, diagnostics);
+            trytes.extend_from_slice(&word_to_trytes(&encode_instruction(&inst)));
+        }
+        Some(Directive::Word) => {
+            for (index, text) in stmt.operands.iter().enumerate() {
+                let at = base + address + trytes.len() as i64;
+                let value = resolve_value(text, RelocationKind::Abs27, at, resolver)
+                    .and_then(|value| check_range(value, WORD_MAX, "a 27-trit word"))
+                    .map_err(|e| stmt.operand_error(index, e))?;
+                trytes.extend_from_slice(&word_to_trytes(&i64_to_word(value)));
+            }
+        }
+        Some(Directive::Tryte) => {
+            for (index, text) in stmt.operands.iter().enumerate() {
+                let at = base + address + trytes.len() as i64;
+                let value = resolve_value(text, RelocationKind::Abs9, at, resolver)
+                    .and_then(|value| check_range(value, TRYTE_MAX, "a 9-trit tryte"))
+                    .map_err(|e| stmt.operand_error(index, e))?;
+                trytes.push(i64_to_tryte(value));
+            }
+        }
+        Some(Directive::String) => {
+            let text = parse_string_literal(&stmt.operands[0]).map_err(|e| stmt.operand_error(0, e))?;
+            for c in text.chars() {
+                let code = check_range(c as i64, TRYTE_MAX, "a 9-trit tryte").map_err(|_| {
+                    stmt.operand_error(
+                        0,
+                        format!("character '{}' (U+{:04X}) does not fit in a tryte", c, c as u32),
+                    )
+                })?;
+                trytes.push(i64_to_tryte(code));
+            }
+            trytes.push([Trit::Z; 9]);
+        }
+        // .zero, .org and .align only move the location counter; the gap stays zero-filled.
+        Some(Directive::Zero | Directive::Org | Directive::Align) => {}
+        // Symbol declarations were handled in pass 1.
+        Some(Directive::Global | Directive::Extern | Directive::Equ) => {}
+    }
+
+    let start = address as usize;
+    if image.len() < next as usize {
+        image.resize(next as usize, [Trit::Z; 9]);
+    }
+    image[start..start + trytes.len()].copy_from_slice(&trytes);
+
+    Ok(())
+}
+
+/// Warns about an instruction that assembles but cannot do what was written:
+/// a result written to R0, or a shift or rotation count the CPU does not use as is.
+fn lint_instruction(stmt: &Statement, inst: &Instruction, diagnostics: &mut Vec<Diagnostic>) {
+    let writes_rd = matches!(inst.opcode.format(), Format::ThreeReg | Format::TwoReg | Format::RegImm);
+    if writes_rd && inst.rd == 0 {
+        diagnostics.push(stmt.operand_warning(
+            0,
+            format!("{} writes R0, which is always zero; the result is discarded", stmt.mnemonic),
+        ));
+    }
+
+    match inst.opcode {
+        Opcode::SHL | Opcode::SHR if inst.imm.abs() >= 27 => diagnostics.push(stmt.operand_warning(
+            2,
+            format!("shifting by {} trits moves every trit out of the word; the result is always zero", inst.imm),
+        )),
+        Opcode::ROTL | Opcode::ROTR if !(0..27).contains(&inst.imm) => diagnostics.push(stmt.operand_warning(
+            2,
+            format!(
+                "rotation count {} is truncated modulo 27; this rotates by {}",
+                inst.imm,
+                inst.imm.rem_euclid(27)
+            ),
+        )),
+        _ => {}
+    }
+}
+
+/// Fails unless `stmt` has exactly `count` operands.
+fn expect_operands(stmt: &Statement, count: usize) -> Result<(), String> {
+    if stmt.operands.len() != count {
+        return Err(format!(
+            "{} expects {} operand(s), found {}",
+            stmt.mnemonic,
+            count,
+            stmt.operands.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `value` lies within `-max..=max`.
+fn check_range(value: i64, max: i64, what: &str) -> Result<i64, String> {
+    if !(-max..=max).contains(&value) {
+        return Err(format!("value {} does not fit in {} ({}..{})", value, what, -max, max));
+    }
+    Ok(value)
+}
+
+/// Checks that a value fits the 12-trit Imm/Offset field.
+fn check_imm_range(value: i64) -> Result<i64, String> {
+    if !(IMM_MIN..=IMM_MAX).contains(&value) {
+        return Err(format!(
+            "immediate {} does not fit in 12 trits ({}..{})",
+            value, IMM_MIN, IMM_MAX
+        ));
+    }
+    Ok(value)
+}
+
+/// Evaluates an operand that must be a plain number, such as a `.zero` count.
+fn evaluate_constant(text: &str, lookup: &Lookup) -> Result<i64, String> {
+    let value = evaluate(text, lookup)?;
+    match value.address {
+        Some(address) => Err(format!(
+            "expected a constant, but '{}' depends on the address of '{}'",
+            text, address.symbol
+        )),
+        None => Ok(value.to_i64()),
+    }
+}
+
+/// Evaluates the expression operand `text` against the completed layout.
+fn evaluate_operand(text: &str, resolver: &Resolver) -> Result<Value, String> {
+    let (layout, relocatable) = (resolver.layout, resolver.relocatable);
+    evaluate(text, &|name| layout.lookup(name, relocatable))
+}
+
+/// Resolves a value operand at `address`: a number, or an address relocated as
+/// `kind` in an object.
+fn resolve_value(text: &str, kind: RelocationKind, address: i64, resolver: &mut Resolver) -> Result<i64, String> {
+    let value = evaluate_operand(text, resolver)?;
+    Ok(resolver.resolve(value, kind, address))
+}
+
+/// Resolves an immediate operand and checks that it fits the Imm/Offset field.
+fn resolve_imm(text: &str, address: i64, resolver: &mut Resolver) -> Result<i64, String> {
+    check_imm_range(resolve_value(text, RelocationKind::Abs12, address, resolver)?)
+}
+
+/// Resolves a PC-relative operand. An address becomes the distance from the
+/// instruction at `address` to it; a plain number is used as a raw offset.
+fn resolve_offset(text: &str, address: i64, resolver: &mut Resolver) -> Result<i64, String> {
+    let value = evaluate_operand(text, resolver)?;
+    if value.address.is_none() {
+        return check_imm_range(value.to_i64());
+    }
+
+    let offset = resolver.resolve(value, RelocationKind::Rel12, address);
+    if !(IMM_MIN..=IMM_MAX).contains(&offset) {
+        let what = if is_symbol_name(text) { "label" } else { "target" };
+        return Err(format!(
+            "{} '{}' is out of range (offset {} does not fit in 12 trits)",
+            what, text, offset
+        ));
+    }
+    Ok(offset)
+}
+
+/// Builds an Instruction from a single statement according to its opcode's format.
+/// `address` is the tryte address the instruction will occupy.
+fn build_instruction(stmt: &Statement, address: i64, resolver: &mut Resolver) -> Result<Instruction, Diagnostic> {
+    let opcode = Opcode::from_mnemonic(&stmt.mnemonic).ok_or_else(|| {
+        if stmt.mnemonic.starts_with('.') {
+            stmt.error(format!("unknown directive '{}'", stmt.mnemonic))
+        } else {
+            stmt.error(format!("unknown mnemonic '{}'", stmt.mnemonic))
+        }
+    })?;
+    let format = opcode.format();
+    let ops = &stmt.operands;
+
+    let expected = match format {
+        // The exit-code register is optional; a bare HALT exits with R0 (zero).
+        Format::Halt if ops.is_empty() => 0,
+        Format::None => 0,
+        Format::Jump | Format::Halt => 1,
+        Format::Branch | Format::TwoReg => 2,
+        Format::ThreeReg | Format::RegImm | Format::Store => 3,
+    };
+    expect_operands(stmt, expected).map_err(|e| stmt.error(e))?;
+
+    let register = |index: usize| parse_register(&ops[index]).map_err(|e| stmt.operand_error(index, e));
+    let imm = |index: usize, resolver: &mut Resolver| {
+        resolve_imm(&ops[index], address, resolver).map_err(|e| stmt.operand_error(index, e))
+    };
+    let offset = |index: usize, resolver: &mut Resolver| {
+        resolve_offset(&ops[index], address, resolver).map_err(|e| stmt.operand_error(index, e))
+    };
+
+    let mut inst = Instruction {
+        opcode,
+        ..Default::default()
+    };
+
+    match format {
+        Format::None => {}
+        Format::ThreeReg => {
+            inst.rd = register(0)?;
+            inst.rs1 = register(1)?;
+            inst.rs2 = register(2)?;
+        }
+        Format::TwoReg => {
+            inst.rd = register(0)?;
+            inst.rs1 = register(1)?;
+        }
+        Format::RegImm => {
+            inst.rd = register(0)?;
+            inst.rs1 = register(1)?;
+            inst.imm = imm(2, resolver)?;
+        }
+        Format::Store => {
+            inst.rs2 = register(0)?;
+            inst.rs1 = register(1)?;
+            inst.imm = imm(2, resolver)?;
+        }
+        Format::Jump => {
+            inst.imm = offset(0, resolver)?;
+        }
+        Format::Branch => {
+            inst.rs1 = register(0)?;
+            inst.imm = offset(1, resolver)?;
+        }
+        Format::Halt => {
+            if !ops.is_empty() {
+                inst.rs1 = register(0)?;
+            }
+        }
+    }
+
+    Ok(inst)
+}
@@ -0,0 +1,128 @@
+// diagnostic.rs - Errors and warnings with source locations.
+//
+// basm does not stop at the first problem: every stage records what it finds
+// and carries on, and the driver prints everything at the end, each entry with
+// the offending source line and a caret under the part it refers to:
+//
+//     error: immediate 300000 does not fit in 12 trits (-265720..265720)
+//      --> prog.basm:12:18
+//        |
+//     12 |     ADDI R1, R0, 300000
+//        |                  ^^^^^^
+//
+// Code produced by a macro is reported at its outermost call site, with one
+// note per expansion level.
+//
+// Warnings about silently truncated immediates cover only shift counts of 27
+// or more and rotation counts outside 0..27, because those are the only
+// immediates the CPU reduces: an immediate that does not fit its 12-trit
+// field is an error, and a load or store whose effective address falls
+// outside memory faults at run time rather than wrapping. The check runs on
+// the resolved value, so counts computed from `.equ` expressions are covered.
+
+use std::fmt::{self, Display};
+
+/// A range of bytes on one source line.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    /// 1-based source line number.
+    pub line: usize,
+    /// Byte offset of the first character within the line.
+    pub start: usize,
+    /// Byte offset just past the last character.
+    pub end: usize,
+}
+
+/// How serious a diagnostic is. Any error means no output is written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// An error or warning about a span of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    /// Extra context, such as the macro expansions that produced the code.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Display) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            span,
+            message: message.to_string(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(span: Span, message: impl Display) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.to_string(),
+            ..Diagnostic::error(span, "")
+        }
+    }
+
+    pub fn with_notes(mut self, notes: Vec<String>) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    /// Formats the diagnostic with the source line it refers to. `path` names the
+    /// file in the location line.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let Span { line, start, end } = self.span;
+        let text = source.lines().nth(line.wrapping_sub(1)).unwrap_or("");
+        let start = start.min(text.len());
+        let end = end.clamp(start, text.len());
+        let column = text[..start].chars().count() + 1;
+
+        let gutter = " ".repeat(line.to_string().len());
+        // Keep tabs in the padding so the caret lines up with the source text.
+        let padding: String = text[..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let carets = "^".repeat(text[start..end].chars().count().max(1));
+
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        out.push_str(&format!("{}--> {}:{}:{}\n", gutter, path, line, column));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line, text));
+        out.push_str(&format!("{} | {}{}\n", gutter, padding, carets));
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        out
+    }
+}
+
+/// Returns true if any of `diagnostics` is an error.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// Orders `diagnostics` by source position and drops exact repeats, which arise
+/// when both passes reject the same statement.
+pub fn sort_diagnostics(diagnostics: &mut Vec<Diagnostic>) {
+    diagnostics.sort_by_key(|d| (d.span.line, d.span.start));
+    let mut seen: Vec<Diagnostic> = Vec::new();
+    diagnostics.retain(|d| {
+        let repeat = seen.contains(d);
+        if !repeat {
+            seen.push(d.clone());
+        }
+        !repeat
+    });
+}
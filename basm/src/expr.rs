@@ -0,0 +1,290 @@
+// expr.rs - Evaluates constant expressions in operands.
+//
+// Anywhere basm expects a number, it accepts an expression:
+//
+//     expr    = term { ("+" | "-") term }
+//     term    = unary { ("*" | "/") unary }
+//     unary   = ("+" | "-") unary | primary
+//     primary = literal | symbol | "(" expr ")"
+//
+// Literals are decimal (`42`), trit strings (`0t+0-`) or heptavintimal (`0h1Z`),
+// as accepted by parse_immediate. A trit literal takes every `+`, `0` and `-`
+// that follows it, so separate it from an operator with a space (`0t+- - 1`).
+// Symbols are labels and `.equ` constants.
+//
+// Arithmetic is done on 27-trit Words with the btern_core routines the CPU
+// uses, so `/` rounds to the nearest integer like DIV. A result that does not
+// fit in a Word is an error rather than wrapping.
+//
+// A label's address is only known once the program is laid out (or linked), so
+// it may only be added to or subtracted from: `table + 3` is an address, and
+// `end - start` is a plain number when both labels are in the same section.
+
+use btern_core::{checked_add_words, checked_mul_words, div_words, i64_to_word, neg_word, word_to_i64, Word, WORD_MAX};
+
+use crate::assembler::Section;
+use crate::parser::{is_symbol_name, parse_immediate, parse_register};
+
+/// What a label's address is measured from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Base {
+    /// A final tryte address, known when assembling an executable.
+    Absolute,
+    /// An offset into a section of a relocatable object.
+    Section(Section),
+    /// A `.extern` symbol defined in another object.
+    External,
+}
+
+/// The label an address-valued expression is relative to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    /// Label used for a relocation against the expression.
+    pub symbol: String,
+    pub base: Base,
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub struct Value {
+    /// The value, including the label's address or offset if there is one.
+    pub word: Word,
+    /// Set when the value is a label's address plus or minus a constant.
+    pub address: Option<Address>,
+}
+
+impl Value {
+    /// A plain number.
+    pub fn constant(word: Word) -> Self {
+        Value { word, address: None }
+    }
+
+    /// Returns the value as an integer.
+    pub fn to_i64(&self) -> i64 {
+        word_to_i64(&self.word)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Word),
+    Symbol(String),
+    Operator(char),
+    Open,
+    Close,
+}
+
+/// Splits `text` into tokens.
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        match c {
+            _ if c.is_whitespace() => {}
+            '+' | '-' | '*' | '/' => tokens.push(Token::Operator(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '0'..='9' => {
+                let trit_literal = c == '0' && chars.get(i) == Some(&'t');
+                if trit_literal {
+                    i += 1;
+                    while i < chars.len() && matches!(chars[i], '+' | '0' | '-') {
+                        i += 1;
+                    }
+                } else {
+                    while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                        i += 1;
+                    }
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let value = parse_immediate(&literal)?;
+                if !(-WORD_MAX..=WORD_MAX).contains(&value) {
+                    return Err(format!("literal {} does not fit in a 27-trit word", literal));
+                }
+                tokens.push(Token::Number(i64_to_word(value)));
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                if !is_symbol_name(&name) && parse_register(&name).is_ok() {
+                    return Err(format!("register {} cannot be used in an expression", name));
+                }
+                tokens.push(Token::Symbol(name));
+            }
+            _ => return Err(format!("unexpected character '{}' in expression '{}'", c, text)),
+        }
+    }
+
+    if tokens.is_empty() {
+        return Err("expected a value, found nothing".to_string());
+    }
+    Ok(tokens)
+}
+
+/// Returns the symbol names `text` refers to, in order, without evaluating it.
+pub fn symbol_names(text: &str) -> Result<Vec<String>, String> {
+    Ok(tokenize(text)?
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Symbol(name) => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Evaluates the expression `text`, calling `lookup` for the value of each symbol.
+pub fn evaluate(text: &str, lookup: &dyn Fn(&str) -> Result<Value, String>) -> Result<Value, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        text,
+        tokens: &tokens,
+        pos: 0,
+        lookup,
+    };
+    let value = parser.expr()?;
+    if parser.pos < tokens.len() {
+        return Err(format!("unexpected {} in expression '{}'", parser.describe(), text));
+    }
+    Ok(value)
+}
+
+/// Recursive-descent evaluator over the token list.
+struct Parser<'a> {
+    text: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Result<Value, String>,
+}
+
+impl Parser<'_> {
+    /// Describes the next token for error messages.
+    fn describe(&self) -> String {
+        match self.tokens.get(self.pos) {
+            None => "end of expression".to_string(),
+            Some(Token::Number(word)) => format!("number {}", word_to_i64(word)),
+            Some(Token::Symbol(name)) => format!("'{}'", name),
+            Some(Token::Operator(op)) => format!("'{}'", op),
+            Some(Token::Open) => "'('".to_string(),
+            Some(Token::Close) => "')'".to_string(),
+        }
+    }
+
+    /// Consumes the next token if it is one of `operators`.
+    fn operator(&mut self, operators: &[char]) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) if operators.contains(op) => {
+                self.pos += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value, String> {
+        let mut value = self.term()?;
+        while let Some(op) = self.operator(&['+', '-']) {
+            let rhs = self.term()?;
+            value = if op == '+' { add(value, rhs)? } else { subtract(value, rhs)? };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.operator(&['*', '/']) {
+            let rhs = self.unary()?;
+            let (lhs, rhs) = (constant_operand(value, op)?, constant_operand(rhs, op)?);
+            let result = if op == '*' {
+                checked_mul_words(&lhs, &rhs)
+            } else {
+                Some(div_words(&lhs, &rhs).ok_or("division by zero in expression")?.0)
+            };
+            value = Value::constant(result.ok_or_else(overflow)?);
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        match self.operator(&['+', '-']) {
+            Some('+') => self.unary(),
+            Some(_) => Ok(Value::constant(neg_word(&constant_operand(self.unary()?, '-')?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        match token {
+            Some(Token::Number(word)) => {
+                self.pos += 1;
+                Ok(Value::constant(word))
+            }
+            Some(Token::Symbol(name)) => {
+                self.pos += 1;
+                (self.lookup)(&name)
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let value = self.expr()?;
+                if self.tokens.get(self.pos) != Some(&Token::Close) {
+                    return Err(format!("expected ')' in expression '{}', found {}", self.text, self.describe()));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(format!("expected a value in expression '{}', found {}", self.text, self.describe())),
+        }
+    }
+}
+
+fn overflow() -> String {
+    "expression overflows a 27-trit word".to_string()
+}
+
+/// Returns the Word of an operand of `op`, which must not be an address.
+fn constant_operand(value: Value, op: char) -> Result<Word, String> {
+    match value.address {
+        None => Ok(value.word),
+        Some(address) => Err(format!(
+            "cannot apply '{}' to the address of '{}'; addresses can only be added to or subtracted from",
+            op, address.symbol
+        )),
+    }
+}
+
+fn add(lhs: Value, rhs: Value) -> Result<Value, String> {
+    if let (Some(a), Some(b)) = (&lhs.address, &rhs.address) {
+        return Err(format!("cannot add the addresses of '{}' and '{}'", a.symbol, b.symbol));
+    }
+    Ok(Value {
+        word: checked_add_words(&lhs.word, &rhs.word).ok_or_else(overflow)?,
+        address: lhs.address.or(rhs.address),
+    })
+}
+
+/// Subtracts `rhs` from `lhs`. The difference of two addresses measured from the
+/// same base is a plain number.
+fn subtract(lhs: Value, rhs: Value) -> Result<Value, String> {
+    let address = match (lhs.address, rhs.address) {
+        (lhs_address, None) => lhs_address,
+        (Some(a), Some(b)) if a.base == b.base && a.base != Base::External => None,
+        (Some(a), Some(b)) => {
+            return Err(format!(
+                "cannot subtract the address of '{}' from '{}': they are not in the same section",
+                b.symbol, a.symbol
+            ))
+        }
+        (None, Some(b)) => return Err(format!("cannot subtract the address of '{}' from a number", b.symbol)),
+    };
+    Ok(Value {
+        word: checked_add_words(&lhs.word, &neg_word(&rhs.word)).ok_or_else(overflow)?,
+        address,
+    })
+}
@@ -0,0 +1,193 @@
+// listing.rs - Side-by-side assembly listings (`basm --listing`).
+//
+// Every source line is printed next to what it assembled to: the tryte address,
+// the encoded trits (most significant first) and the same value in heptavintimal.
+// Instruction words are split into their fields, most significant first (opcode,
+// rd, rs1, rs2, imm), so the layout produced by encode_instruction can be checked
+// against what bemu decodes.
+// A statement that emits several Words (a `.word` list, an `LI` expansion, a
+// macro invocation) gets one row per Word. The listing ends with a symbol table
+// and a cross-reference of the lines that use each symbol.
+
+use btern_core::{i64_to_word, trits_to_heptavintimal, trits_to_string, Trit, Tryte, TRYTES_PER_WORD};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Trit widths of the instruction fields, most significant first: opcode, rd, rs1, rs2, imm.
+const FIELD_WIDTHS: [usize; 5] = [6, 3, 3, 3, 12];
+
+/// Width of the trits column: a Word split into its five fields.
+const TRITS_WIDTH: usize = 27 + FIELD_WIDTHS.len() - 1;
+
+/// What one statement assembled to.
+#[derive(Debug, Clone)]
+pub struct Emission {
+    /// Source line of the statement (the call site, for code from a macro).
+    pub line: usize,
+    /// Tryte address of the first tryte (a section offset in an object).
+    pub address: i64,
+    /// The trytes written, least significant first within each Word.
+    pub trytes: Vec<Tryte>,
+    /// Zero trytes reserved by `.zero`, `.org` or `.align`.
+    pub reserved: i64,
+    /// True for an instruction, whose Word is shown split into fields.
+    pub instruction: bool,
+}
+
+/// A symbol in the listing's symbol table.
+#[derive(Debug, Clone)]
+pub struct ListedSymbol {
+    pub name: String,
+    /// The label's address or the constant's value.
+    pub value: i64,
+    /// Where the symbol lives: a section name, `.equ` or `extern`.
+    pub kind: &'static str,
+    /// Line of the definition (none for `.extern` symbols).
+    pub line: Option<usize>,
+}
+
+/// Everything needed to print a listing, collected during pass 2.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    pub emissions: Vec<Emission>,
+    pub symbols: Vec<ListedSymbol>,
+    /// Lines that use each symbol, in ascending order.
+    pub references: BTreeMap<String, Vec<usize>>,
+}
+
+/// Formats trits most significant first, split into instruction fields if `instruction` is set.
+fn format_trits(trits: &[Trit], instruction: bool) -> String {
+    let text = trits_to_string(trits);
+    if !instruction {
+        return text;
+    }
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for width in FIELD_WIDTHS {
+        fields.push(&text[start..start + width]);
+        start += width;
+    }
+    fields.join(" ")
+}
+
+impl Listing {
+    /// Records that `name` is used on `line`.
+    pub fn reference(&mut self, name: &str, line: usize) {
+        let lines = self.references.entry(name.to_string()).or_default();
+        if !lines.contains(&line) {
+            lines.push(line);
+            lines.sort_unstable();
+        }
+    }
+
+    /// Renders the listing of `source`, titled with `path`.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut by_line: BTreeMap<usize, Vec<&Emission>> = BTreeMap::new();
+        for emission in &self.emissions {
+            by_line.entry(emission.line).or_default().push(emission);
+        }
+
+        let mut out = String::new();
+        writeln!(out, "; basm listing of {}", path).unwrap();
+        writeln!(
+            out,
+            "; address, trits (most significant first; instructions as opcode rd rs1 rs2 imm), heptavintimal, line, source"
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        let blank = " ".repeat(5 + 2 + TRITS_WIDTH + 2 + 9);
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut rows = Vec::new();
+            for emission in by_line.get(&line).into_iter().flatten() {
+                rows.extend(emission_rows(emission));
+            }
+            if rows.is_empty() {
+                rows.push(blank.clone());
+            }
+            for (row_index, row) in rows.iter().enumerate() {
+                if row_index == 0 {
+                    writeln!(out, "{}  {:>5}  {}", row, line, text).unwrap();
+                } else {
+                    writeln!(out, "{}", row.trim_end()).unwrap();
+                }
+            }
+        }
+
+        self.render_symbols(&mut out);
+        out
+    }
+
+    /// Appends the symbol table, by value, and the cross-reference, by name.
+    fn render_symbols(&self, out: &mut String) {
+        let name_width = self.symbols.iter().map(|s| s.name.len()).max().unwrap_or(0).max(6);
+
+        let mut symbols: Vec<&ListedSymbol> = self.symbols.iter().collect();
+        symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+        writeln!(out, "\n; Symbol table").unwrap();
+        writeln!(out, "{:<name_width$}  {:>14}  {:<9}  {:<7}  Line", "Symbol", "Value", "Hept", "Section").unwrap();
+        for symbol in &symbols {
+            let word = i64_to_word(symbol.value);
+            let line = symbol.line.map_or("-".to_string(), |line| line.to_string());
+            writeln!(
+                out,
+                "{:<name_width$}  {:>14}  {:<9}  {:<7}  {}",
+                symbol.name,
+                symbol.value,
+                trits_to_heptavintimal(&word),
+                symbol.kind,
+                line
+            )
+            .unwrap();
+        }
+
+        let mut names: Vec<&ListedSymbol> = self.symbols.iter().collect();
+        names.sort_by(|a, b| a.name.cmp(&b.name));
+        writeln!(out, "\n; Cross-reference").unwrap();
+        writeln!(out, "{:<name_width$}  {:>7}  Used on lines", "Symbol", "Defined").unwrap();
+        for symbol in names {
+            let defined = symbol.line.map_or("-".to_string(), |line| line.to_string());
+            let used = match self.references.get(&symbol.name) {
+                Some(lines) => lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join(", "),
+                None => "(unused)".to_string(),
+            };
+            writeln!(out, "{:<name_width$}  {:>7}  {}", symbol.name, defined, used).unwrap();
+        }
+    }
+}
+
+/// Formats the address, trits and heptavintimal columns for one emission, one row per Word.
+fn emission_rows(emission: &Emission) -> Vec<String> {
+    if emission.trytes.is_empty() {
+        let note = match emission.reserved {
+            0 => String::new(),
+            count => format!("({} zero trytes)", count),
+        };
+        return vec![format!(
+            "{:05}  {:<trits$}  {:<9}",
+            emission.address,
+            note,
+            "",
+            trits = TRITS_WIDTH
+        )];
+    }
+
+    emission
+        .trytes
+        .chunks(TRYTES_PER_WORD)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let trits: Vec<Trit> = chunk.iter().flatten().copied().collect();
+            // Only a whole Word can be an instruction.
+            let instruction = emission.instruction && chunk.len() == TRYTES_PER_WORD;
+            format!(
+                "{:05}  {:<trits_width$}  {:<9}",
+                emission.address + (index * TRYTES_PER_WORD) as i64,
+                format_trits(&trits, instruction),
+                trits_to_heptavintimal(&trits),
+                trits_width = TRITS_WIDTH
+            )
+        })
+        .collect()
+}
@@ -1,82 +1,218 @@
 // main.rs - The entry point for the btern assembler (basm).
 
-use btern_core::{Word, Opcode, Instruction, encode_instruction};
-use std::fs::File;
+use btern_core::exe::{write_executable, Executable};
+use btern_core::image::{write_dense_image, write_image, write_raw_image, ProgramImage};
+use btern_core::object::write_object;
+use btern_core::TRYTES_PER_WORD;
+use diagnostic::{Diagnostic, Severity};
+use std::env;
+use std::fs::{self, File};
 use std::io::Write;
+use std::process;
+use std::path::{Path, PathBuf};
 
-// Helper function to convert a Word ([Trit; 27]) into a raw byte vector (54 bytes, 2 bits per trit).
-fn word_to_raw_bytes(word: &Word) -> Vec<u8> {
-    // We use a simple representation: 1 byte per trit, storing the i8 value (-1, 0, or 1).
-    let mut bytes = Vec::with_capacity(27);
+mod assembler;
+mod diagnostic;
+mod expr;
+mod listing;
+mod parser;
+mod pseudo;
 
-    for trit in word.iter() {
-        // Convert the Trit enum to its i8 representation (-1, 0, 1) and then cast to u8 for writing.
-        // We rely on the emulator to cast it back to i8 and validate.
-        bytes.push(trit.to_i8() as u8);
+const USAGE: &str = "\
+Usage: basm <source.basm> [-o <output.bin>] [--format <FORMAT>] [--strip] [--listing <file>]
+       basm -c <source.basm> [-o <output.o>] [--listing <file>]
+
+Formats:
+  exe   Executable with segments, entry point and symbols (default)
+  bct   Flat packed image, 4 trits per byte, with entry point header
+  dense Flat dense image, 5 trits per byte, with entry point header
+  raw   Legacy flat image, one byte per trit
+
+Options:
+  -c        Write a relocatable object for bld instead of an executable
+  --strip   Leave the symbol table out of an executable
+  --listing Write a listing of addresses, encoded trits and source lines,
+            followed by a symbol table and cross-reference";
+
+/// Output file formats basm can write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OutputFormat {
+    Exe,
+    Bct,
+    Dense,
+    Raw,
+}
+
+/// Command-line options accepted by basm.
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    format: OutputFormat,
+    strip: bool,
+    /// Where to write the assembly listing, if requested.
+    listing: Option<PathBuf>,
+    /// Write a relocatable object (`-c`) instead of a loadable program.
+    object: bool,
+}
+
+/// Parses the command-line arguments. The output defaults to the input path with a
+/// `.bin` extension, or `.o` for an object.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut format = None;
+    let mut strip = false;
+    let mut listing = None;
+    let mut object = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let path = iter.next().ok_or_else(|| format!("{} requires a path\n{}", arg, USAGE))?;
+                output = Some(PathBuf::from(path));
+            }
+            "-f" | "--format" => {
+                format = match iter.next().map(String::as_str) {
+                    Some("exe") => Some(OutputFormat::Exe),
+                    Some("bct") => Some(OutputFormat::Bct),
+                    Some("dense") => Some(OutputFormat::Dense),
+                    Some("raw") => Some(OutputFormat::Raw),
+                    Some(other) => return Err(format!("Unknown format '{}'\n{}", other, USAGE)),
+                    None => return Err(format!("{} requires a format\n{}", arg, USAGE)),
+                };
+            }
+            "--strip" => strip = true,
+            "--listing" => {
+                let path = iter.next().ok_or_else(|| format!("{} requires a path\n{}", arg, USAGE))?;
+                listing = Some(PathBuf::from(path));
+            }
+            "-c" => object = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'\n{}", arg, USAGE)),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument '{}'\n{}", arg, USAGE)),
+        }
     }
 
-    // A Word is 3 Trytes (27 trits), resulting in 27 bytes per instruction.
-    bytes
+    if object && (format.is_some() || strip) {
+        return Err(format!("-c cannot be combined with --format or --strip\n{}", USAGE));
+    }
+
+    let input: PathBuf = input.ok_or_else(|| USAGE.to_string())?;
+    let extension = if object { "o" } else { "bin" };
+    let output = output.unwrap_or_else(|| Path::new(&input).with_extension(extension));
+    Ok(Options {
+        input,
+        output,
+        format: format.unwrap_or(OutputFormat::Exe),
+        strip,
+        listing,
+        object,
+    })
 }
 
-fn main() -> Result<(), String> {
+/// Assembles the input file and writes the output (and listing) `options` asks for.
+fn assemble_file(options: &Options) -> Result<(), String> {
     println!("Starting btern Assembler (basm)...");
 
-    // --- Test Program Definition ---
-    
-    // 1. ADDI R1, R0, 5 (R1 = 5)
-    let inst1 = Instruction {
-        opcode: Opcode::ADDI,
-        rd: 1,
-        rs1: 0,
-        rs2: 0,
-        imm: 5,
-    };
+    let source = fs::read_to_string(&options.input)
+        .map_err(|e| format!("Failed to read {}: {}", options.input.display(), e))?;
 
-    // 2. ADDI R2, R0, 10 (R2 = 10)
-    let inst2 = Instruction {
-        opcode: Opcode::ADDI,
-        rd: 2,
-        rs1: 0,
-        rs2: 0,
-        imm: 10,
-    };
+    // --- Assembly and Encoding ---
+    let mut diagnostics = Vec::new();
+    if options.object {
+        let assembled = assembler::assemble_object(&source, &mut diagnostics);
+        report(&options.input, &source, &diagnostics);
+        let Some((object, listing)) = assembled else { process::exit(1) };
+        if let Some(path) = &options.listing {
+            write_listing(path, &listing.render(&options.input.display().to_string(), &source))?;
+        }
+        let object_data = write_object(&object);
+        println!(
+            "Assembled {} section(s), {} symbol(s), {} relocation(s) -> {} bytes",
+            object.sections.len(),
+            object.symbols.len(),
+            object.relocations.len(),
+            object_data.len()
+        );
+        return write_output(&options.output, &object_data);
+    }
 
-    // 3. ADD R3, R1, R2 (R3 = 15)
-    let inst3 = Instruction {
-        opcode: Opcode::ADD,
-        rd: 3,
-        rs1: 1,
-        rs2: 2,
-        imm: 0,
+    let assembled = assembler::assemble(&source, &mut diagnostics);
+    report(&options.input, &source, &diagnostics);
+    let Some((mut exe, listing)) = assembled else { process::exit(1) };
+    if let Some(path) = &options.listing {
+        write_listing(path, &listing.render(&options.input.display().to_string(), &source))?;
+    }
+    if options.strip {
+        exe.symbols.clear();
+    }
+    let program_data = match options.format {
+        OutputFormat::Exe => write_executable(&exe),
+        OutputFormat::Bct => write_image(&flatten(&exe)?),
+        OutputFormat::Dense => write_dense_image(&flatten(&exe)?),
+        OutputFormat::Raw => {
+            if exe.entry != 0 {
+                eprintln!("Warning: the raw format cannot record entry point {}; it will start at 0", exe.entry);
+            }
+            write_raw_image(&flatten(&exe)?.trytes)
+        }
     };
+    let trytes = exe.total_trytes();
+    println!(
+        "Assembled {} Trytes ({} Words) in {} segment(s) -> {} bytes, entry point {}",
+        trytes,
+        trytes.div_ceil(TRYTES_PER_WORD),
+        exe.segments.len(),
+        program_data.len(),
+        exe.entry
+    );
 
-    // 4. HALT
-    let inst4 = Instruction {
-        opcode: Opcode::HALT,
-        rd: 0,
-        rs1: 0,
-        rs2: 0,
-        imm: 0,
-    };
+    write_output(&options.output, &program_data)
+}
 
-    let program = vec![inst1, inst2, inst3, inst4];
-    let mut raw_program_data = Vec::new();
-    
-    // --- Assembly and Encoding ---
-    for (i, inst) in program.iter().enumerate() {
-        let word = encode_instruction(inst);
-        let raw_bytes = word_to_raw_bytes(&word);
-        raw_program_data.extend_from_slice(&raw_bytes);
-        println!("Instruction {}: {:?} -> {} bytes", i, inst.opcode, raw_bytes.len());
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = parse_args(&args).and_then(|options| assemble_file(&options)) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Lays `exe` out as a flat image for the formats without segments.
+fn flatten(exe: &Executable) -> Result<ProgramImage, String> {
+    exe.flatten().map_err(|e| e.to_string())
+}
+
+/// Prints every diagnostic with its source line, followed by a count.
+fn report(path: &Path, source: &str, diagnostics: &[Diagnostic]) {
+    let path = path.display().to_string();
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(&path, source));
     }
 
-    // --- Write to File ---
-    let output_path = "test_program.bin";
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        eprintln!("{}: {} error(s), {} warning(s); nothing written", path, errors, warnings);
+    } else if warnings > 0 {
+        eprintln!("{}: {} warning(s)", path, warnings);
+    }
+}
+
+/// Writes the listing text to `path`.
+fn write_listing(path: &Path, text: &str) -> Result<(), String> {
+    fs::write(path, text).map_err(|e| format!("Failed to write listing {}: {}", path.display(), e))?;
+    println!("Wrote listing to {}", path.display());
+    Ok(())
+}
+
+/// Writes the assembled bytes to `output_path`.
+fn write_output(output_path: &Path, data: &[u8]) -> Result<(), String> {
     let mut file = File::create(output_path).map_err(|e| format!("Failed to create file: {}", e))?;
-    file.write_all(&raw_program_data).map_err(|e| format!("Failed to write to file: {}", e))?;
+    file.write_all(data).map_err(|e| format!("Failed to write to file: {}", e))?;
 
-    println!("Successfully assembled program to {}", output_path);
-    
+    println!("Successfully assembled program to {}", output_path.display());
     Ok(())
-}
\ No newline at end of file
+}
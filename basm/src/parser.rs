@@ -0,0 +1,616 @@
+// parser.rs - Splits basm source text into statements and parses operands.
+//
+// Macros are expanded here, so the assembler only ever sees plain statements:
+//
+//     .macro push reg
+//         SUBI SP, SP, 3
+//         STW  \reg, SP, 0
+//     .endm
+//
+// Inside the body, `\name` is replaced by the argument for parameter `name`,
+// `\@` by a number unique to each expansion (for local labels such as
+// `loop\@:`), and `\()` by nothing, to separate a parameter from following
+// text. A macro must be defined before it is used; its body may invoke other
+// macros, up to MAX_MACRO_DEPTH expansions deep.
+
+use btern_core::{
+    trits_from_heptavintimal, trits_from_str, trits_to_i64, HEPTAVINTIMAL_DIGITS, NUM_REGISTERS, REG_AT, REG_LR,
+    REG_SP,
+};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::diagnostic::{Diagnostic, Span};
+
+/// Maximum nesting of macro expansions, which stops runaway recursion.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// One level of macro expansion that produced a statement or label.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    /// Name of the expanded macro.
+    pub name: String,
+    /// Source line of the `.macro` directive.
+    pub defined_line: usize,
+    /// Source line of the body line that was expanded.
+    pub body_line: usize,
+}
+
+/// Describes the macro expansions that produced a statement or label, outermost
+/// first, as diagnostic notes. Runs of the same expansion (recursion) are shown
+/// once with a repeat count.
+fn expansion_notes(expansions: &[Expansion]) -> Vec<String> {
+    let mut notes = Vec::new();
+    let mut frames = expansions.iter().peekable();
+    while let Some(expansion) = frames.next() {
+        let mut repeats = 1;
+        while frames
+            .next_if(|next| next.name == expansion.name && next.body_line == expansion.body_line)
+            .is_some()
+        {
+            repeats += 1;
+        }
+        let mut note = format!(
+            "in macro '{}' (defined on line {}), line {}",
+            expansion.name, expansion.defined_line, expansion.body_line
+        );
+        if repeats > 1 {
+            note.push_str(&format!(" (x{})", repeats));
+        }
+        notes.push(note);
+    }
+    notes
+}
+
+/// A single instruction parsed from one line of assembly source.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// Source location of the whole statement, used for diagnostics. For code
+    /// produced by a macro, this is the outermost invocation.
+    pub span: Span,
+    /// The mnemonic as written in the source (case is preserved).
+    pub mnemonic: String,
+    /// Comma-separated operands, trimmed of surrounding whitespace.
+    pub operands: Vec<String>,
+    /// Source location of each operand (the statement's span inside a macro).
+    pub operand_spans: Vec<Span>,
+    /// Macro expansions that produced this statement, outermost first.
+    pub expansions: Vec<Expansion>,
+}
+
+impl Statement {
+    /// Source line of the statement (of the outermost invocation, inside a macro).
+    pub fn line(&self) -> usize {
+        self.span.line
+    }
+
+    /// An error about this statement, naming the macros that produced it if any.
+    pub fn error(&self, message: impl Display) -> Diagnostic {
+        Diagnostic::error(self.span, message).with_notes(expansion_notes(&self.expansions))
+    }
+
+    /// An error about operand `index`.
+    pub fn operand_error(&self, index: usize, message: impl Display) -> Diagnostic {
+        Diagnostic {
+            span: self.operand_span(index),
+            ..self.error(message)
+        }
+    }
+
+    /// A warning about this statement.
+    pub fn warning(&self, message: impl Display) -> Diagnostic {
+        Diagnostic::warning(self.span, message).with_notes(expansion_notes(&self.expansions))
+    }
+
+    /// A warning about operand `index`.
+    pub fn operand_warning(&self, index: usize, message: impl Display) -> Diagnostic {
+        Diagnostic {
+            span: self.operand_span(index),
+            ..self.warning(message)
+        }
+    }
+
+    fn operand_span(&self, index: usize) -> Span {
+        self.operand_spans.get(index).copied().unwrap_or(self.span)
+    }
+}
+
+/// A label definition (`name:`), which names the current location.
+#[derive(Debug, Clone)]
+pub struct Label {
+    /// Source location of the name, used for diagnostics.
+    pub span: Span,
+    pub name: String,
+    /// Macro expansions that produced this label, outermost first.
+    pub expansions: Vec<Expansion>,
+}
+
+impl Label {
+    /// An error about this label, naming the macros that produced it if any.
+    pub fn error(&self, message: impl Display) -> Diagnostic {
+        Diagnostic::error(self.span, message).with_notes(expansion_notes(&self.expansions))
+    }
+}
+
+/// One element of a source file, in source order.
+#[derive(Debug, Clone)]
+pub enum Item {
+    Label(Label),
+    Statement(Statement),
+}
+
+/// Removes a trailing `;` comment from a source line, ignoring `;` inside string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits an operand list on commas that are not inside string literals.
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(&text[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(&text[start..]);
+    operands
+}
+
+/// Returns true if `name` is a valid symbol: a letter, `_` or `.` followed by
+/// letters, digits, `_` or `.`, and not a register name.
+pub fn is_symbol_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.');
+    valid_start
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && parse_register(name).is_err()
+}
+
+/// A macro definition collected from `.macro` ... `.endm`.
+#[derive(Debug, Clone)]
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    /// Source line of the `.macro` directive.
+    line: usize,
+    /// Body lines with their source line numbers, not yet substituted.
+    body: Vec<(usize, String)>,
+}
+
+/// Expands macros while parsing. Invocations are numbered for `\@`.
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    expansion_count: usize,
+}
+
+/// Returns true if `name` is a valid macro or parameter name.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// If `text` is a `.macro` or `.endm` directive, returns its lowercased name and the rest of the line.
+fn macro_directive(text: &str) -> Option<(String, &str)> {
+    let (word, rest) = match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (text, ""),
+    };
+    let word = word.to_ascii_lowercase();
+    matches!(word.as_str(), ".macro" | ".endm").then_some((word, rest))
+}
+
+/// Parses the `name param, ...` part of a `.macro` directive.
+fn parse_macro_header(rest: &str, line: usize) -> Result<Macro, String> {
+    let mut words = rest.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty());
+    let name = words.next().ok_or(".macro requires a name")?;
+    if !is_identifier(name) {
+        return Err(format!("invalid macro name '{}'", name));
+    }
+
+    let mut params: Vec<String> = Vec::new();
+    for param in words {
+        if !is_identifier(param) {
+            return Err(format!("invalid parameter name '{}' in macro '{}'", param, name));
+        }
+        if params.iter().any(|p| p == param) {
+            return Err(format!("duplicate parameter '{}' in macro '{}'", param, name));
+        }
+        params.push(param.to_string());
+    }
+
+    Ok(Macro {
+        name: name.to_string(),
+        params,
+        line,
+        body: Vec::new(),
+    })
+}
+
+/// Replaces `\param`, `\@` and `\()` in one body line. A backslash followed by
+/// anything else (such as the `\n` escape in a string) is left alone.
+fn substitute(text: &str, params: &[String], args: &[String], unique: usize) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find('\\') {
+        result.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        if let Some(tail) = after.strip_prefix('@') {
+            result.push_str(&format!("__{}", unique));
+            rest = tail;
+        } else if let Some(tail) = after.strip_prefix("()") {
+            rest = tail;
+        } else {
+            let len = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+            match params.iter().position(|p| *p == after[..len]) {
+                Some(index) => {
+                    result.push_str(&args[index]);
+                    rest = &after[len..];
+                }
+                None => {
+                    result.push('\\');
+                    rest = after;
+                }
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+impl Preprocessor {
+    /// Parses `lines` (source line number and text), appending labels and statements
+    /// to `items` and problems to `diagnostics`. Inside a macro expansion, `call_span`
+    /// is the outermost invocation and `expansions` describes the expansions being parsed.
+    fn parse_lines(
+        &mut self,
+        lines: &[(usize, String)],
+        call_span: Option<Span>,
+        expansions: &[Expansion],
+        items: &mut Vec<Item>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        // The macro being defined, and whether it will be kept (a bad header still
+        // swallows the body, so it is not parsed as code).
+        let mut defining: Option<(Macro, bool)> = None;
+
+        for (line_number, raw_line) in lines {
+            let line_number = *line_number;
+            let mut frames = expansions.to_vec();
+            if let Some(innermost) = frames.last_mut() {
+                innermost.body_line = line_number;
+            }
+            // Code from a macro is reported at the outermost call site.
+            let locate = |span: Span| call_span.unwrap_or(span);
+            let notes = expansion_notes(&frames);
+            let fail = |span: Span, message: String| Diagnostic::error(locate(span), message).with_notes(notes.clone());
+
+            let uncommented = strip_comment(raw_line);
+            let text = uncommented.trim();
+            let start = uncommented.len() - uncommented.trim_start().len();
+            let line_span = Span {
+                line: line_number,
+                start,
+                end: start + text.len(),
+            };
+
+            match (macro_directive(text), defining.as_mut()) {
+                (Some((directive, _)), Some(_)) if directive == ".macro" => {
+                    diagnostics.push(fail(line_span, "a macro cannot be defined inside another macro".to_string()));
+                    continue;
+                }
+                (Some((directive, _)), Some(_)) if directive == ".endm" => {
+                    let (definition, keep) = defining.take().unwrap();
+                    if keep {
+                        self.macros.insert(definition.name.to_ascii_lowercase(), definition);
+                    }
+                    continue;
+                }
+                (_, Some((definition, _))) => {
+                    definition.body.push((line_number, raw_line.clone()));
+                    continue;
+                }
+                (Some((directive, rest)), None) if directive == ".macro" => {
+                    let definition = match parse_macro_header(rest, line_number) {
+                        Ok(definition) => definition,
+                        Err(e) => {
+                            diagnostics.push(fail(line_span, e));
+                            let placeholder = Macro {
+                                name: String::new(),
+                                params: Vec::new(),
+                                line: line_number,
+                                body: Vec::new(),
+                            };
+                            defining = Some((placeholder, false));
+                            continue;
+                        }
+                    };
+                    let key = definition.name.to_ascii_lowercase();
+                    let problem = if let Some(previous) = self.macros.get(&key) {
+                        Some(format!(
+                            "macro '{}' is already defined on line {}",
+                            definition.name, previous.line
+                        ))
+                    } else if crate::assembler::is_reserved_mnemonic(&definition.name) {
+                        Some(format!(
+                            "macro name '{}' conflicts with an instruction or directive",
+                            definition.name
+                        ))
+                    } else {
+                        None
+                    };
+                    let keep = problem.is_none();
+                    diagnostics.extend(problem.map(|e| fail(line_span, e)));
+                    defining = Some((definition, keep));
+                    continue;
+                }
+                (Some(_), None) => {
+                    diagnostics.push(fail(line_span, ".endm without a matching .macro".to_string()));
+                    continue;
+                }
+                (None, None) => {}
+            }
+
+            let ParsedLine { labels, statement } = match parse_line(uncommented, line_number) {
+                Ok(parsed) => parsed,
+                Err((span, e)) => {
+                    diagnostics.push(fail(span, e));
+                    continue;
+                }
+            };
+            items.extend(labels.into_iter().map(|(name, span)| {
+                Item::Label(Label {
+                    span: locate(span),
+                    name,
+                    expansions: frames.clone(),
+                })
+            }));
+            let Some(mut statement) = statement else { continue };
+            if let Some(call_span) = call_span {
+                statement.span = call_span;
+                statement.operand_spans.fill(call_span);
+            }
+            statement.expansions = frames.clone();
+
+            let Some(definition) = self.macros.get(&statement.mnemonic.to_ascii_lowercase()).cloned() else {
+                items.push(Item::Statement(statement));
+                continue;
+            };
+
+            if frames.len() >= MAX_MACRO_DEPTH {
+                diagnostics.push(statement.error(format!(
+                    "macro '{}' nested too deeply (limit {} expansions)",
+                    definition.name, MAX_MACRO_DEPTH
+                )));
+                continue;
+            }
+            if statement.operands.len() != definition.params.len() {
+                diagnostics.push(statement.error(format!(
+                    "macro '{}' expects {} argument(s), found {}",
+                    definition.name,
+                    definition.params.len(),
+                    statement.operands.len()
+                )));
+                continue;
+            }
+
+            self.expansion_count += 1;
+            let body: Vec<(usize, String)> = definition
+                .body
+                .iter()
+                .map(|(n, text)| {
+                    let text = substitute(text, &definition.params, &statement.operands, self.expansion_count);
+                    (*n, text)
+                })
+                .collect();
+            frames.push(Expansion {
+                name: definition.name.clone(),
+                defined_line: definition.line,
+                body_line: definition.line,
+            });
+            self.parse_lines(&body, Some(statement.span), &frames, items, diagnostics);
+        }
+
+        if let Some((definition, _)) = defining {
+            let span = call_span.unwrap_or(Span {
+                line: definition.line,
+                ..Span::default()
+            });
+            let message = format!(".macro '{}' has no matching .endm", definition.name);
+            diagnostics.push(Diagnostic::error(span, message).with_notes(expansion_notes(expansions)));
+        }
+    }
+}
+
+/// The parts of one source line, before macro expansion.
+struct ParsedLine {
+    /// Label names and their locations.
+    labels: Vec<(String, Span)>,
+    /// The statement, if the line has one (without macro expansions yet).
+    statement: Option<Statement>,
+}
+
+/// Splits one comment-free source line (number `line`) into its label names and,
+/// if present, a statement. Spans are byte offsets into `text`. On failure,
+/// returns the location of the problem and a message.
+fn parse_line(text: &str, line: usize) -> Result<ParsedLine, (Span, String)> {
+    let span = |start: usize, end: usize| Span { line, start, end };
+    let mut labels = Vec::new();
+    let mut pos = 0;
+
+    // Peel off any leading label definitions (a ':' inside a string literal is not one).
+    loop {
+        let rest = &text[pos..];
+        let Some(colon) = rest[..rest.find('"').unwrap_or(rest.len())].find(':') else { break };
+        let name = rest[..colon].trim();
+        let name_start = pos + (rest.len() - rest.trim_start().len()).min(colon);
+        let name_span = span(name_start, name_start + name.len());
+        if !is_symbol_name(name) {
+            return Err((name_span, format!("invalid label name '{}'", name)));
+        }
+        labels.push((name.to_string(), name_span));
+        pos += colon + 1;
+    }
+
+    let rest = &text[pos..];
+    let start = pos + (rest.len() - rest.trim_start().len());
+    let body = text[start..].trim_end();
+    if body.is_empty() {
+        return Ok(ParsedLine { labels, statement: None });
+    }
+    let end = start + body.len();
+
+    // The mnemonic is everything up to the first whitespace; the rest are operands.
+    let mnemonic_end = body.find(char::is_whitespace).map_or(end, |idx| start + idx);
+    let mut operands = Vec::new();
+    let mut operand_spans = Vec::new();
+    let operand_text = &text[mnemonic_end..end];
+    if !operand_text.trim().is_empty() {
+        let mut offset = mnemonic_end;
+        for piece in split_operands(operand_text) {
+            let operand = piece.trim();
+            let operand_start = offset + (piece.len() - piece.trim_start().len());
+            if operand.is_empty() {
+                return Err((
+                    span(operand_start, operand_start),
+                    format!("empty operand in '{}'", body),
+                ));
+            }
+            operands.push(operand.to_string());
+            operand_spans.push(span(operand_start, operand_start + operand.len()));
+            // Skip the piece and the comma after it.
+            offset += piece.len() + 1;
+        }
+    }
+
+    Ok(ParsedLine {
+        labels,
+        statement: Some(Statement {
+            span: span(start, end),
+            mnemonic: text[start..mnemonic_end].to_string(),
+            operands,
+            operand_spans,
+            expansions: Vec::new(),
+        }),
+    })
+}
+
+/// Parses assembly source text into labels and statements, expanding macros.
+/// Blank lines and comment-only lines are skipped. A label may share its line
+/// with a statement (`loop: ADDI R1, R1, -1`). Lines with errors are reported
+/// in `diagnostics` and left out.
+pub fn parse_source(source: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<Item> {
+    let lines: Vec<(usize, String)> = source
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.to_string()))
+        .collect();
+
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        expansion_count: 0,
+    };
+    let mut items = Vec::new();
+    preprocessor.parse_lines(&lines, None, &[], &mut items, diagnostics);
+    items
+}
+
+/// Parses a register operand: `R0`-`R26` (case-insensitive), or the `AT`/`SP`/`LR` aliases.
+pub fn parse_register(text: &str) -> Result<usize, String> {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "AT" => return Ok(REG_AT),
+        "SP" => return Ok(REG_SP),
+        "LR" => return Ok(REG_LR),
+        _ => {}
+    }
+
+    let index = upper
+        .strip_prefix('R')
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .ok_or_else(|| format!("expected a register, found '{}'", text))?;
+
+    if index >= NUM_REGISTERS {
+        return Err(format!("register '{}' out of range (R0-R26)", text));
+    }
+    Ok(index)
+}
+
+/// Parses an integer literal: signed decimal (`-42`), a balanced ternary trit
+/// string written most significant trit first (`0t+0-` = 8), or heptavintimal,
+/// three trits per digit (`0h1Z` = 26, see HEPTAVINTIMAL_DIGITS).
+pub fn parse_immediate(text: &str) -> Result<i64, String> {
+    if let Some(digits) = text.strip_prefix("0t") {
+        let trits = trits_from_str(digits)
+            .map_err(|_| format!("invalid trit literal '{}' (use only '+', '0' and '-')", text))?;
+        if trits.len() > 27 {
+            return Err(format!("trit literal '{}' is longer than a 27-trit word", text));
+        }
+        return Ok(trits_to_i64(&trits));
+    }
+    if let Some(digits) = text.strip_prefix("0h") {
+        let trits = trits_from_heptavintimal(digits).map_err(|_| {
+            format!(
+                "invalid heptavintimal literal '{}' (use only {})",
+                text, HEPTAVINTIMAL_DIGITS
+            )
+        })?;
+        if trits.len() > 27 {
+            return Err(format!("heptavintimal literal '{}' is longer than a 27-trit word", text));
+        }
+        return Ok(trits_to_i64(&trits));
+    }
+
+    text.parse::<i64>()
+        .map_err(|_| format!("expected an integer immediate, found '{}'", text))
+}
+
+/// Parses a double-quoted string literal, handling the escapes
+/// `\n`, `\t`, `\0`, `\\` and `\"`.
+pub fn parse_string_literal(text: &str) -> Result<String, String> {
+    let body = text
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(|| format!("expected a string literal, found '{}'", text))?;
+
+    let mut result = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some(other) => return Err(format!("unknown escape '\\{}' in string literal", other)),
+            None => return Err("unterminated escape at end of string literal".to_string()),
+        }
+    }
+    Ok(result)
+}
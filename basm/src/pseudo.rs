@@ -0,0 +1,188 @@
+// pseudo.rs - Expands pseudo-instructions into real instructions.
+//
+// Pseudo-instructions are conveniences with no opcode of their own. Each one is
+// replaced by one or more real statements before pass 1, so every later stage
+// (and any error message, which keeps the original line) sees only real code.
+//
+//   MOV Rd, Rs          ADD Rd, Rs, R0
+//   CLR Rd              ADD Rd, R0, R0
+//   NOT Rd, Rs          INV Rd, Rs (tritwise negation)
+//   LI  Rd, value       1-5 instructions for any 27-trit value (see load_immediate)
+//   BEQ Ra, Rb, target  SUB AT, Ra, Rb; BRZ AT, target
+//   BNE Ra, Rb, target  SUB AT, Ra, Rb; BRP AT, target; BRN AT, target
+//   BLT Ra, Rb, target  SUB AT, Ra, Rb; BRN AT, target
+//   BGT Ra, Rb, target  SUB AT, Ra, Rb; BRP AT, target
+//   BLE Ra, Rb, target  SUB AT, Ra, Rb; BRN AT, target; BRZ AT, target
+//   BGE Ra, Rb, target  SUB AT, Ra, Rb; BRP AT, target; BRZ AT, target
+//
+// The compare-and-branch forms clobber AT (R24). They compare by subtraction,
+// so operands whose difference overflows a Word compare incorrectly.
+//
+// A label target is resolved against each branch's own address, so both
+// branches of BNE, BLE and BGE reach it. A plain-number target is a raw offset
+// from the first branch; the second branch sits one Word later, so its operand
+// is rewritten as `(target) - 3` to land on the same address.
+
+use btern_core::{Trit, IMM_MAX, TRYTES_PER_WORD};
+
+use crate::assembler::Constants;
+use crate::diagnostic::Diagnostic;
+use crate::expr::{evaluate, symbol_names, Address, Base, Value};
+use crate::parser::{parse_register, Item, Statement};
+
+/// Shift that moves a value past the 12-trit Imm field.
+const IMM_TRITS: i64 = 12;
+
+/// Returns true if `name` is a pseudo-instruction mnemonic (case-insensitive).
+pub fn is_pseudo(name: &str) -> bool {
+    matches!(
+        name.to_ascii_uppercase().as_str(),
+        "MOV" | "CLR" | "NOT" | "LI" | "BEQ" | "BNE" | "BLT" | "BGT" | "BLE" | "BGE"
+    )
+}
+
+/// Replaces every pseudo-instruction in `items` with the real statements it stands for.
+/// `constants` are the file's `.equ` values, which decide how long an `LI` is.
+/// Pseudo-instructions in error are reported in `diagnostics` and dropped.
+pub fn expand_pseudo_instructions(
+    items: Vec<Item>,
+    constants: &Constants,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Item> {
+    let mut expanded = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            Item::Statement(stmt) if is_pseudo(&stmt.mnemonic) => match expand(&stmt, constants) {
+                Ok(statements) => expanded.extend(statements.into_iter().map(Item::Statement)),
+                Err(e) => diagnostics.push(e),
+            },
+            other => expanded.push(other),
+        }
+    }
+    expanded
+}
+
+/// Builds a real statement that takes its source location from the pseudo-instruction
+/// `stmt`. An operand copied from `stmt` keeps its span, so diagnostics point at it.
+fn derived(stmt: &Statement, mnemonic: &str, operands: &[&str]) -> Statement {
+    let span_of = |op: &&str| match stmt.operands.iter().position(|original| original == op) {
+        Some(index) => stmt.operand_spans.get(index).copied().unwrap_or(stmt.span),
+        None => stmt.span,
+    };
+    Statement {
+        span: stmt.span,
+        mnemonic: mnemonic.to_string(),
+        operands: operands.iter().map(|op| op.to_string()).collect(),
+        operand_spans: operands.iter().map(span_of).collect(),
+        expansions: stmt.expansions.clone(),
+    }
+}
+
+/// Expands one pseudo-instruction.
+fn expand(stmt: &Statement, constants: &Constants) -> Result<Vec<Statement>, Diagnostic> {
+    let mnemonic = stmt.mnemonic.to_ascii_uppercase();
+    let ops: Vec<&str> = stmt.operands.iter().map(String::as_str).collect();
+    // Operand count, and how many of the leading operands are registers.
+    let (expected, registers) = match mnemonic.as_str() {
+        "CLR" => (1, 1),
+        "LI" => (2, 1),
+        "MOV" | "NOT" => (2, 2),
+        _ => (3, 2),
+    };
+    if ops.len() != expected {
+        return Err(stmt.error(format!(
+            "{} expects {} operand(s), found {}",
+            stmt.mnemonic,
+            expected,
+            ops.len()
+        )));
+    }
+    // Check register operands here, so errors name the pseudo-instruction's operands.
+    for (index, op) in ops[..registers].iter().enumerate() {
+        parse_register(op).map_err(|e| stmt.operand_error(index, e))?;
+    }
+
+    let statements = match mnemonic.as_str() {
+        "MOV" => vec![derived(stmt, "ADD", &[ops[0], ops[1], "R0"])],
+        "CLR" => vec![derived(stmt, "ADD", &[ops[0], "R0", "R0"])],
+        "NOT" => vec![derived(stmt, "INV", &[ops[0], ops[1]])],
+        "LI" => load_immediate(stmt, ops[0], ops[1], constants).map_err(|e| stmt.operand_error(1, e))?,
+        _ => {
+            // Every compare-and-branch starts with AT = Ra - Rb, then tests its sign.
+            let branches: &[&str] = match mnemonic.as_str() {
+                "BEQ" => &["BRZ"],
+                "BNE" => &["BRP", "BRN"],
+                "BLT" => &["BRN"],
+                "BGT" => &["BRP"],
+                "BLE" => &["BRN", "BRZ"],
+                _ => &["BRP", "BRZ"],
+            };
+            let mut statements = vec![derived(stmt, "SUB", &["AT", ops[0], ops[1]])];
+            statements.push(derived(stmt, branches[0], &["AT", ops[2]]));
+            if let Some(branch) = branches.get(1) {
+                let target = if is_raw_offset(ops[2], constants) {
+                    format!("({}) - {}", ops[2], TRYTES_PER_WORD)
+                } else {
+                    ops[2].to_string()
+                };
+                statements.push(derived(stmt, branch, &["AT", &target]));
+            }
+            statements
+        }
+    };
+    Ok(statements)
+}
+
+/// Returns true if the branch target `text` is a plain number (a raw offset)
+/// rather than an address. Labels are given a stand-in address, so the
+/// difference of two labels counts as a number, as it does in pass 2.
+/// Malformed expressions count as addresses and are reported by pass 2.
+fn is_raw_offset(text: &str, constants: &Constants) -> bool {
+    let lookup = |name: &str| {
+        Ok(match constants.get(name) {
+            Some(constant) => Value::constant(constant.value),
+            None => Value {
+                word: [Trit::Z; 27],
+                address: Some(Address {
+                    symbol: name.to_string(),
+                    base: Base::Absolute,
+                }),
+            },
+        })
+    };
+    evaluate(text, &lookup).is_ok_and(|value| value.address.is_none())
+}
+
+/// Expands `LI rd, value`. A value that fits the 12-trit Imm field takes one
+/// `ADDI`; larger ones are built 12 trits at a time with `SHL` and `ADDI`, at
+/// most five instructions for a full 27-trit Word. An expression that uses a
+/// label is left to the assembler in a single `ADDI`, so like any label
+/// immediate it must fit in 12 trits.
+fn load_immediate(stmt: &Statement, rd: &str, text: &str, constants: &Constants) -> Result<Vec<Statement>, String> {
+    if symbol_names(text)?.iter().any(|name| !constants.contains_key(name)) {
+        return Ok(vec![derived(stmt, "ADDI", &[rd, "R0", text])]);
+    }
+
+    let lookup = |name: &str| Ok(Value::constant(constants[name].value));
+    let value = evaluate(text, &lookup)?;
+    let mut statements = Vec::new();
+    push_value(stmt, rd, value.to_i64(), &mut statements);
+    Ok(statements)
+}
+
+/// Appends the instructions that load `value` into `rd`, most significant chunk first.
+fn push_value(stmt: &Statement, rd: &str, value: i64, statements: &mut Vec<Statement>) {
+    if (-IMM_MAX..=IMM_MAX).contains(&value) {
+        statements.push(derived(stmt, "ADDI", &[rd, "R0", &value.to_string()]));
+        return;
+    }
+
+    // Split off the low 12 trits as a balanced remainder, so both parts stay in range.
+    let modulus = 3_i64.pow(IMM_TRITS as u32);
+    let low = (value + IMM_MAX).rem_euclid(modulus) - IMM_MAX;
+    push_value(stmt, rd, (value - low) / modulus, statements);
+    statements.push(derived(stmt, "SHL", &[rd, rd, &IMM_TRITS.to_string()]));
+    if low != 0 {
+        statements.push(derived(stmt, "ADDI", &[rd, rd, &low.to_string()]));
+    }
+}
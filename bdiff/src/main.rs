@@ -0,0 +1,358 @@
+// main.rs - The entry point for the btern trace differ (bdiff).
+//
+// bdiff compares two execution traces of the same program, written by
+// `bemu --trace` (see btern_core::trace), e.g. from two bemu builds. It finds
+// the first retired instruction whose record differs, says what differs (PC,
+// instruction, register values read or written, memory access), and shows
+// what led up to it: the last few identical steps, the registers as the
+// traces last saw them, and the last known contents of the memory involved.
+//
+// Exit status, as for diff(1): 0 if the traces are identical, 1 if they
+// diverge, 2 if a trace cannot be read.
+
+use btern_core::trace::{parse_trace, MemoryEffect, TraceRecord};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "\
+Usage: bdiff <a.trace> <b.trace> [options]
+
+Options:
+  --context <N>   Number of identical steps to show before the divergence (default: 8)";
+
+/// Command-line options accepted by bdiff.
+struct Options {
+    paths: [String; 2],
+    context: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut context = 8;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--context" => {
+                let value = iter.next().ok_or("--context requires a value")?;
+                context = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value '{}' for --context", value))?;
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let paths: [String; 2] = paths.try_into().map_err(|_| USAGE.to_string())?;
+    Ok(Options { paths, context })
+}
+
+fn read_trace(path: &str) -> Result<Vec<TraceRecord>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Error reading trace {}: {}", path, e))?;
+    parse_trace(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Formats tryte values as `[a b c]`, least significant first.
+fn format_trytes(values: &[i64]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(" "))
+}
+
+fn describe_memory(memory: &MemoryEffect) -> String {
+    if memory.write {
+        format!(
+            "store {:05} {} -> {}",
+            memory.address,
+            format_trytes(&memory.old),
+            format_trytes(&memory.new)
+        )
+    } else {
+        format!("load {:05} {}", memory.address, format_trytes(&memory.old))
+    }
+}
+
+/// Formats one record on a line: step, PC, instruction and its effects.
+fn describe(record: &TraceRecord) -> String {
+    let mut effects = Vec::new();
+    if !record.reads.is_empty() {
+        let reads: Vec<String> = record
+            .reads
+            .iter()
+            .map(|r| format!("R{}={}", r.register, r.value))
+            .collect();
+        effects.push(format!("reads {}", reads.join(" ")));
+    }
+    for write in &record.writes {
+        effects.push(format!("R{} {} -> {}", write.register, write.old, write.new));
+    }
+    if let Some(memory) = &record.memory {
+        effects.push(describe_memory(memory));
+    }
+    format!(
+        "step {:>7}  {:05}  {:<22}  {}",
+        record.step,
+        record.pc,
+        record.instruction.to_string(),
+        effects.join("; ")
+    )
+}
+
+/// Lists the differences between two records of the same step.
+fn differences(a: &TraceRecord, b: &TraceRecord) -> Vec<String> {
+    let mut found = Vec::new();
+    if a.step != b.step {
+        found.push(format!("step number: {} vs {}", a.step, b.step));
+    }
+    if a.pc != b.pc {
+        found.push(format!("pc: {:05} vs {:05}", a.pc, b.pc));
+    }
+    if a.instruction != b.instruction {
+        found.push(format!("instruction: {} vs {}", a.instruction, b.instruction));
+    }
+
+    let reads =
+        |record: &TraceRecord| -> BTreeMap<usize, i64> { record.reads.iter().map(|r| (r.register, r.value)).collect() };
+    let (reads_a, reads_b) = (reads(a), reads(b));
+    for register in reads_a.keys().chain(reads_b.keys()).collect::<BTreeSet<_>>() {
+        match (reads_a.get(register), reads_b.get(register)) {
+            (Some(x), Some(y)) if x != y => found.push(format!("R{} read: {} vs {}", register, x, y)),
+            (Some(_), None) => found.push(format!("R{} read only in the first trace", register)),
+            (None, Some(_)) => found.push(format!("R{} read only in the second trace", register)),
+            _ => {}
+        }
+    }
+
+    let writes = |record: &TraceRecord| -> BTreeMap<usize, (i64, i64)> {
+        record.writes.iter().map(|w| (w.register, (w.old, w.new))).collect()
+    };
+    let (writes_a, writes_b) = (writes(a), writes(b));
+    for register in writes_a.keys().chain(writes_b.keys()).collect::<BTreeSet<_>>() {
+        match (writes_a.get(register), writes_b.get(register)) {
+            (Some(x), Some(y)) if x != y => found.push(format!(
+                "R{} written: {} -> {} vs {} -> {}",
+                register, x.0, x.1, y.0, y.1
+            )),
+            (Some(_), None) => found.push(format!("R{} written only in the first trace", register)),
+            (None, Some(_)) => found.push(format!("R{} written only in the second trace", register)),
+            _ => {}
+        }
+    }
+
+    if a.memory != b.memory {
+        let memory = |record: &TraceRecord| record.memory.as_ref().map_or("no access".to_string(), describe_memory);
+        found.push(format!("memory: {} vs {}", memory(a), memory(b)));
+    }
+    found
+}
+
+/// What the identical prefix of the traces says about the machine state.
+#[derive(Default)]
+struct State {
+    /// Register values, with the step that last read or wrote them.
+    registers: BTreeMap<usize, (i64, u64)>,
+    /// Tryte values, with the step that last loaded or stored them.
+    memory: BTreeMap<i64, (i64, u64)>,
+}
+
+impl State {
+    fn replay(records: &[TraceRecord]) -> Self {
+        let mut state = State::default();
+        for record in records {
+            for read in &record.reads {
+                state.registers.insert(read.register, (read.value, record.step));
+            }
+            for write in &record.writes {
+                state.registers.insert(write.register, (write.new, record.step));
+            }
+            if let Some(memory) = &record.memory {
+                for (offset, value) in memory.new.iter().enumerate() {
+                    state
+                        .memory
+                        .insert(memory.address + offset as i64, (*value, record.step));
+                }
+            }
+        }
+        state
+    }
+}
+
+/// Prints the identical steps before `index`, the registers and the memory that
+/// the diverging records (`records`, one or two) access.
+fn print_context(records: &[TraceRecord], index: usize, context: usize, diverging: &[&TraceRecord]) {
+    let start = index.saturating_sub(context);
+    if start < index {
+        println!("\nLast {} identical step(s):", index - start);
+        for record in &records[start..index] {
+            println!("  {}", describe(record));
+        }
+    }
+
+    let state = State::replay(&records[..index]);
+    if !state.registers.is_empty() {
+        println!("\nRegisters before the divergence (as last read or written; others never appear in the traces):");
+        for (register, (value, step)) in &state.registers {
+            println!("  R{:<2} = {:>14}  (step {})", register, value, step);
+        }
+    }
+
+    let mut addresses: Vec<i64> = diverging
+        .iter()
+        .filter_map(|record| record.memory.as_ref())
+        .flat_map(|memory| memory.address..memory.address + memory.new.len() as i64)
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    if !addresses.is_empty() {
+        println!("\nMemory accessed by the diverging step, as last loaded or stored before it:");
+        for address in addresses {
+            match state.memory.get(&address) {
+                Some((value, step)) => println!("  {:05} = {:>6}  (step {})", address, value, step),
+                None => println!("  {:05}   not accessed earlier", address),
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let [path_a, path_b] = &options.paths;
+    let (a, b) = match (read_trace(path_a), read_trace(path_b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let common = a.len().min(b.len());
+    let index = (0..common).find(|&i| a[i] != b[i]);
+
+    match index {
+        Some(index) => {
+            println!("{} and {} diverge at step {}:", path_a, path_b, a[index].step);
+            println!("  {}: {}", path_a, describe(&a[index]));
+            println!("  {}: {}", path_b, describe(&b[index]));
+            println!("\nDifferences:");
+            for difference in differences(&a[index], &b[index]) {
+                println!("  {}", difference);
+            }
+            print_context(&a, index, options.context, &[&a[index], &b[index]]);
+        }
+        None if a.len() == b.len() => {
+            println!("{} and {} are identical ({} steps).", path_a, path_b, a.len());
+            return;
+        }
+        None => {
+            // One trace stopped early: the program faulted or hit a step limit there.
+            let (short, long, records) = if a.len() < b.len() {
+                (path_a, path_b, &b)
+            } else {
+                (path_b, path_a, &a)
+            };
+            println!("{} ends after {} steps; {} continues with:", short, common, long);
+            println!("  {}", describe(&records[common]));
+            print_context(records, common, options.context, &[&records[common]]);
+        }
+    }
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btern_core::trace::{RegisterRead, RegisterWrite};
+    use btern_core::{Instruction, Opcode};
+
+    fn record(step: u64, reads: &[(usize, i64)], writes: &[(usize, i64, i64)]) -> TraceRecord {
+        TraceRecord {
+            step,
+            pc: 3 * (step as i64 - 1),
+            instruction: Instruction {
+                opcode: Opcode::ADD,
+                rd: 3,
+                rs1: 1,
+                rs2: 2,
+                imm: 0,
+            },
+            reads: reads
+                .iter()
+                .map(|&(register, value)| RegisterRead { register, value })
+                .collect(),
+            writes: writes
+                .iter()
+                .map(|&(register, old, new)| RegisterWrite { register, old, new })
+                .collect(),
+            memory: None,
+        }
+    }
+
+    fn store(step: u64, address: i64, old: &[i64], new: &[i64]) -> TraceRecord {
+        let mut record = record(step, &[], &[]);
+        record.memory = Some(MemoryEffect {
+            write: true,
+            address,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        });
+        record
+    }
+
+    #[test]
+    fn identical_records_have_no_differences() {
+        let a = record(1, &[(1, 2), (2, 3)], &[(3, 0, 5)]);
+        assert!(differences(&a, &a.clone()).is_empty());
+    }
+
+    #[test]
+    fn differences_name_each_field() {
+        let a = record(4, &[(1, 2), (2, 3)], &[(3, 0, 5)]);
+        let mut b = record(4, &[(1, 2), (2, 4), (7, 0)], &[(3, 0, 6), (9, 1, 1)]);
+        b.pc = 30;
+        b.instruction.opcode = Opcode::SUB;
+        assert_eq!(
+            differences(&a, &b),
+            [
+                "pc: 00009 vs 00030",
+                "instruction: ADD R3, R1, R2 vs SUB R3, R1, R2",
+                "R2 read: 3 vs 4",
+                "R7 read only in the second trace",
+                "R3 written: 0 -> 5 vs 0 -> 6",
+                "R9 written only in the second trace",
+            ]
+        );
+
+        let a = store(5, 30, &[0, 0, 0], &[1, 0, 0]);
+        let b = record(5, &[], &[]);
+        assert_eq!(
+            differences(&a, &b),
+            ["memory: store 00030 [0 0 0] -> [1 0 0] vs no access"]
+        );
+    }
+
+    #[test]
+    fn replay_keeps_the_last_value_and_step() {
+        let records = [
+            record(1, &[(0, 0)], &[(1, 0, 5)]),
+            record(2, &[(1, 5)], &[(2, 0, 7)]),
+            store(3, 30, &[0, 0, 0], &[7, 0, 0]),
+            record(4, &[(2, 7)], &[(1, 5, -1)]),
+            store(5, 31, &[0], &[4]),
+        ];
+        let state = State::replay(&records);
+        let registers: Vec<_> = state.registers.into_iter().collect();
+        assert_eq!(registers, [(0, (0, 1)), (1, (-1, 4)), (2, (7, 4))]);
+        let memory: Vec<_> = state.memory.into_iter().collect();
+        assert_eq!(memory, [(30, (7, 3)), (31, (4, 5)), (32, (0, 3))]);
+    }
+}
@@ -0,0 +1,128 @@
+// bdiff.rs - Runs bdiff on small trace files and checks its verdict and exit status.
+
+use btern_core::trace::{RegisterRead, RegisterWrite, TraceRecord};
+use btern_core::{Instruction, Opcode};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A trace of `count` steps of `ADDI R1, R1, 1`, counting R1 up from 0.
+fn counting(count: u64) -> Vec<TraceRecord> {
+    (1..=count)
+        .map(|step| TraceRecord {
+            step,
+            pc: 3 * (step as i64 - 1),
+            instruction: Instruction {
+                opcode: Opcode::ADDI,
+                rd: 1,
+                rs1: 1,
+                rs2: 0,
+                imm: 1,
+            },
+            reads: vec![RegisterRead {
+                register: 1,
+                value: step as i64 - 1,
+            }],
+            writes: vec![RegisterWrite {
+                register: 1,
+                old: step as i64 - 1,
+                new: step as i64,
+            }],
+            memory: None,
+        })
+        .collect()
+}
+
+/// Writes `records` as a trace file named after the test and `name`.
+fn write_trace(test: &str, name: &str, records: &[TraceRecord]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("bdiff-{}-{}-{}.trace", test, name, std::process::id()));
+    let text: String = records.iter().map(|r| format!("{}\n", r.to_json())).collect();
+    fs::write(&path, text).unwrap();
+    path
+}
+
+/// Runs bdiff on two traces; returns its exit status and stdout and stderr.
+fn bdiff(test: &str, a: &[TraceRecord], b: &[TraceRecord], args: &[&str]) -> (i32, String, String) {
+    let (path_a, path_b) = (write_trace(test, "a", a), write_trace(test, "b", b));
+    let output = Command::new(env!("CARGO_BIN_EXE_bdiff"))
+        .arg(&path_a)
+        .arg(&path_b)
+        .args(args)
+        .output()
+        .unwrap();
+    fs::remove_file(path_a).unwrap();
+    fs::remove_file(path_b).unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn identical_traces_exit_with_0() {
+    let (status, stdout, _) = bdiff("identical", &counting(5), &counting(5), &[]);
+    assert_eq!(status, 0);
+    assert!(stdout.contains("are identical (5 steps)"), "{}", stdout);
+}
+
+#[test]
+fn diverging_traces_report_the_first_difference() {
+    let mut b = counting(10);
+    b[6].writes[0].new = 100;
+    b[8].pc = 99;
+    let (status, stdout, _) = bdiff("diverging", &counting(10), &b, &["--context", "2"]);
+    assert_eq!(status, 1);
+    assert!(stdout.contains("diverge at step 7:"), "{}", stdout);
+    assert!(stdout.contains("R1 written: 6 -> 7 vs 6 -> 100"), "{}", stdout);
+    assert!(
+        !stdout.contains("pc:"),
+        "only the first divergence is reported:\n{}",
+        stdout
+    );
+    // Two identical steps of context, and R1 as step 6 left it.
+    assert!(stdout.contains("Last 2 identical step(s):"), "{}", stdout);
+    assert!(
+        stdout.contains("step       5") && stdout.contains("step       6"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("step       4"), "{}", stdout);
+    assert!(stdout.contains("R1  =              6  (step 6)"), "{}", stdout);
+}
+
+#[test]
+fn a_trace_that_ends_early_is_reported() {
+    let (status, stdout, _) = bdiff("short", &counting(10), &counting(4), &[]);
+    assert_eq!(status, 1);
+    assert!(stdout.contains("ends after 4 steps;"), "{}", stdout);
+    assert!(stdout.contains("continues with:\n  step       5"), "{}", stdout);
+}
+
+#[test]
+fn an_unreadable_trace_exits_with_2() {
+    let good = write_trace("unreadable", "good", &counting(3));
+    let bad = std::env::temp_dir().join(format!("bdiff-unreadable-bad-{}.trace", std::process::id()));
+    fs::write(&bad, format!("{}\n{{\"step\":\n", counting(1)[0].to_json())).unwrap();
+    let missing = std::env::temp_dir().join("bdiff-no-such-file.trace");
+
+    let run = |a: &PathBuf, b: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_bdiff"))
+            .arg(a)
+            .arg(b)
+            .output()
+            .unwrap()
+    };
+    let output = run(&good, &bad);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 2: "), "{}", stderr);
+    let output = run(&missing, &good);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Error reading trace"));
+
+    fs::remove_file(good).unwrap();
+    fs::remove_file(bad).unwrap();
+}
@@ -0,0 +1,213 @@
+// main.rs - The entry point for the btern disassembler (bdis).
+//
+// bdis prints a program one Word (3 Trytes) per line as basm source: the
+// decoded instruction, followed by a comment with the tryte address and the raw
+// trits. Words that do not look like instructions, and everything outside
+// executable segments, are printed as `.word` data, so the output can be fed
+// back into basm. Symbols from the executable are printed as labels.
+
+use btern_core::exe::{read_program, Executable, Segment};
+use btern_core::{
+    decode_instruction, encode_instruction, trits_to_i64, trits_to_string, trytes_to_word, word_to_i64, Format,
+    Instruction, Opcode, Tryte, TRYTES_PER_WORD,
+};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "Usage: bdis <program.bin>";
+
+/// How a single Word of the image is interpreted.
+enum Line {
+    Code(Instruction),
+    Data(i64),
+}
+
+/// Returns true if every Instruction field that the opcode's format does not use is zero.
+/// Words with stray bits in unused fields would not survive a round trip through basm,
+/// and are far more likely to be data than hand-written code.
+fn uses_only_format_fields(inst: &Instruction) -> bool {
+    let (rd, rs1, rs2, imm) = match inst.opcode.format() {
+        Format::None => (false, false, false, false),
+        Format::ThreeReg => (true, true, true, false),
+        Format::TwoReg => (true, true, false, false),
+        Format::RegImm => (true, true, false, true),
+        Format::Store => (false, true, true, true),
+        Format::Jump => (false, false, false, true),
+        Format::Branch => (false, true, false, true),
+        Format::Halt => (false, true, false, false),
+    };
+    (rd || inst.rd == 0) && (rs1 || inst.rs1 == 0) && (rs2 || inst.rs2 == 0) && (imm || inst.imm == 0)
+}
+
+/// Classifies one Word as code or data. An all-zero Word decodes as NOP, but is
+/// treated as data (padding, `.zero`, a zero `.word`) when it follows data or an
+/// instruction that never falls through (`HALT`, `JMP`, `RET`).
+fn classify(trytes: &[Tryte], zero_is_data: bool) -> Line {
+    let word = trytes_to_word(trytes);
+    match decode_instruction(&word) {
+        Ok(inst) if inst == Instruction::default() && zero_is_data => Line::Data(0),
+        Ok(inst) if uses_only_format_fields(&inst) && encode_instruction(&inst) == word => Line::Code(inst),
+        _ => Line::Data(word_to_i64(&word)),
+    }
+}
+
+/// Maps tryte addresses to the names of the labels defined there.
+type Labels<'a> = HashMap<i64, Vec<&'a str>>;
+
+/// Prints the label definitions at `address`, if any.
+fn print_labels(labels: &Labels, address: i64) {
+    for name in labels.get(&address).into_iter().flatten() {
+        println!("{}:", name);
+    }
+}
+
+/// Prints a run of `count` zero Trytes starting at `address` as `.zero`,
+/// broken up wherever a label falls inside it.
+fn print_zero_fill(labels: &Labels, address: i64, count: usize) {
+    let end = address + count as i64;
+    let mut start = address;
+    while start < end {
+        print_labels(labels, start);
+        let next = labels.keys().copied().filter(|&a| a > start && a < end).min().unwrap_or(end);
+        let text = format!(".zero {}", next - start);
+        println!("        {:<28}; {:05}", text, start);
+        start = next;
+    }
+}
+
+/// Prints the initialized contents of one segment. Executable segments are decoded
+/// as instructions where possible; everything else is printed as data.
+fn disassemble_segment(segment: &Segment, labels: &Labels) {
+    let data = &segment.data;
+    let mut in_data = false;
+    let mut after_stop = false;
+    let mut offset = 0;
+    while offset < data.len() {
+        let address = segment.address + offset as i64;
+        print_labels(labels, address);
+
+        // A label in the middle of a Word splits it into separate Trytes.
+        let whole_word = offset + TRYTES_PER_WORD <= data.len()
+            && (1..TRYTES_PER_WORD as i64).all(|i| !labels.contains_key(&(address + i)));
+        if !whole_word {
+            let tryte = &data[offset];
+            let text = format!(".tryte {}", trits_to_i64(tryte));
+            println!("        {:<28}; {:05}  {}", text, address, trits_to_string(tryte));
+            in_data = true;
+            offset += 1;
+            continue;
+        }
+
+        let chunk = &data[offset..offset + TRYTES_PER_WORD];
+        let trits = trits_to_string(&trytes_to_word(chunk));
+        let line = if segment.permissions.execute {
+            classify(chunk, in_data || after_stop)
+        } else {
+            Line::Data(word_to_i64(&trytes_to_word(chunk)))
+        };
+
+        let (text, note) = match line {
+            Line::Code(inst) => {
+                after_stop = matches!(inst.opcode, Opcode::HALT | Opcode::JMP | Opcode::RET);
+                let note = match inst.opcode.format() {
+                    Format::Jump | Format::Branch => {
+                        let target = address + inst.imm;
+                        match labels.get(&target) {
+                            Some(names) => format!("  -> {:05} {}", target, names[0]),
+                            None => format!("  -> {:05}", target),
+                        }
+                    }
+                    _ => String::new(),
+                };
+                if in_data {
+                    println!("; --- code ---");
+                    in_data = false;
+                }
+                (inst.to_string(), note)
+            }
+            Line::Data(value) => {
+                if !in_data && segment.permissions.execute {
+                    println!("; --- data ---");
+                }
+                in_data = true;
+                (format!(".word {}", value), String::new())
+            }
+        };
+        println!("        {:<28}; {:05}  {}{}", text, address, trits, note);
+        offset += TRYTES_PER_WORD;
+    }
+
+    // Space beyond the initialized contents (all of it, for .bss) is zero-filled.
+    if segment.size > data.len() {
+        print_zero_fill(labels, segment.address + data.len() as i64, segment.size - data.len());
+    }
+}
+
+/// Prints the disassembly of `exe` to stdout.
+fn disassemble(name: &str, exe: &Executable) {
+    println!(
+        "; bdis: {} ({} Trytes in {} segment(s), entry point {})",
+        name,
+        exe.total_trytes(),
+        exe.segments.len(),
+        exe.entry
+    );
+    println!("; Comments show the tryte address and the raw trits, most significant first.");
+
+    let mut labels = Labels::new();
+    for symbol in &exe.symbols {
+        labels.entry(symbol.address).or_default().push(&symbol.name);
+    }
+    // Without a symbol table, still mark a non-zero entry point so it survives reassembly.
+    if exe.entry != 0 && exe.symbol("_start").is_none() {
+        labels.entry(exe.entry).or_default().push("_start");
+    }
+
+    for segment in &exe.segments {
+        println!();
+        println!(
+            "; segment {}: {} Trytes at address {}, {}",
+            segment.name, segment.size, segment.address, segment.permissions
+        );
+        if matches!(segment.name.as_str(), ".text" | ".data" | ".bss") {
+            println!("{}", segment.name);
+        }
+        disassemble_segment(segment, &labels);
+
+        // A label just past the segment (e.g. a stack top) belongs after its last
+        // item, unless another segment starts there and prints it.
+        let end = segment.address + segment.size as i64;
+        if !exe.segments.iter().any(|s| s.address <= end && end < s.address + s.size as i64) {
+            print_labels(&labels, end);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match args.as_slice() {
+        [path] if !path.starts_with('-') => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Error reading program file {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    match read_program(&bytes) {
+        Ok(program) => disassemble(path, &program),
+        Err(e) => {
+            eprintln!("Error loading program: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -1,8 +1,31 @@
 // cpu.rs - Defines the CPU structure and its primary operations.
 
-use btern_core::{add_words, neg_word, word_to_i64, trits_to_i64, i64_to_word, Word, Tryte, Trit, Instruction, Opcode};
+use btern_core::{trits_to_heptavintimal, trits_to_i64, trits_to_string, abs_word, add_words, consensus_words, div_words, max_words, min_words, mul_words, neg_word, rotl_word, rotr_word, shl_word, shr_word, decode_instruction, sign_word, word_to_i64, i64_to_word, Word, Tryte, Trit, Instruction, Opcode, TRYTES_PER_WORD};
+use btern_core::exe::{read_program, Executable};
+use btern_core::trace::{MemoryEffect, RegisterRead, RegisterWrite, TraceRecord};
+use std::io::Write;
+
+/// Default size of main memory.
+pub const MEMORY_TRYTES: usize = 19683; // 3^9 Trytes
+
+/// Whether a data access read or wrote memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
 
-const MEMORY_TRYTES: usize = 19683; // 3^9 Trytes
+/// A data memory access made by LDW, STW, LDT or STT. Instruction fetches are not recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    /// Tryte address of the first tryte accessed.
+    pub address: usize,
+    /// The trytes before the access.
+    pub old: Vec<Tryte>,
+    /// The trytes after the access (the same as `old` for a read).
+    pub new: Vec<Tryte>,
+}
 
 pub struct Cpu {
     /// General-Purpose Registers R0-R26.
@@ -13,86 +36,196 @@ pub struct Cpu {
 
     /// Simulated main memory.
     memory: Vec<Tryte>,
+
+    /// Exit code recorded when the program halts (None while running).
+    halt_code: Option<i64>,
+
+    /// The data access made by the last instruction executed, if any.
+    last_access: Option<MemoryAccess>,
 }
 
 impl Cpu {
-    /// Converts a raw byte (which should be -1, 0, or 1) into a Trit.
-    fn byte_to_trit(byte: u8) -> Result<Trit, String> {
-        let val = byte as i8;
-        match Trit::from_i8(val) {
-            Ok(t) => Ok(t),
-            Err(_) => Err(format!("Invalid trit value in program binary: {}", val)),
+    /// Loads a program (an executable or a flat image) into memory, placing each
+    /// segment at its load address, and points the PC at its entry point.
+    /// Returns the decoded program.
+    pub fn load_program(&mut self, program_bytes: &[u8]) -> Result<Executable, String> {
+        let exe = read_program(program_bytes).map_err(|e| e.to_string())?;
+
+        for segment in &exe.segments {
+            let start = segment.address as usize;
+            if start.checked_add(segment.size).is_none_or(|end| end > self.memory.len()) {
+                return Err(format!(
+                    "Segment {} ({} Trytes at address {}) exceeds memory size of {} Trytes.",
+                    segment.name,
+                    segment.size,
+                    segment.address,
+                    self.memory.len()
+                ));
+            }
+            // Memory starts zeroed, so only the initialized part needs copying.
+            self.memory[start..start + segment.data.len()].copy_from_slice(&segment.data);
         }
+
+        self.set_pc(exe.entry);
+        Ok(exe)
     }
 
-    /// Loads a raw byte program into memory.
-    /// Assumes the byte stream contains sequential i8 representations of Trits.
-    pub fn load_program(&mut self, program_bytes: &[u8]) -> Result<(), String> {
-        let trits_per_tryte = 9;
-        let mut current_tryte_idx = 0;
-        let mut current_trit_in_tryte = 0;
+    /// Creates a new, initialized CPU instance with `memory_trytes` Trytes of main memory.
+    pub fn new(memory_trytes: usize) -> Self {
+        Self {
+            // R0 is not special-cased here, but in the instruction logic.
+            // All registers default to a word of Zeros.
+            gpr: [[Trit::Z; 27]; 27],
+            pc: [Trit::Z; 27],
+            memory: vec![[Trit::Z; 9]; memory_trytes], // Trit::Z is imported from btern_core
+            halt_code: None,
+            last_access: None,
+        }
+    }
 
-        if program_bytes.len() % trits_per_tryte != 0 {
-            return Err(format!(
-                "Program size is not a multiple of 9 trits (1 Tryte). Size: {} bytes",
-                program_bytes.len()
-            ));
+    /// Sets the Program Counter, e.g. to start execution at a program's entry point.
+    pub fn set_pc(&mut self, address: i64) {
+        self.pc = i64_to_word(address);
+    }
+
+    /// Returns the Program Counter as a tryte address.
+    pub fn pc(&self) -> i64 {
+        word_to_i64(&self.pc)
+    }
+
+    /// Returns the value of register `index` (0-26).
+    pub fn register(&self, index: usize) -> Word {
+        self.gpr[index]
+    }
+
+    /// Sets register `index` (0-26). Like the instruction handlers, writes to R0 are discarded.
+    pub fn set_register(&mut self, index: usize, value: Word) {
+        if index != 0 {
+            self.gpr[index] = value;
         }
+    }
 
-        for byte in program_bytes {
-            if current_tryte_idx >= self.memory.len() {
-                return Err("Program exceeds maximum memory size.".to_string());
-            }
+    /// Returns main memory.
+    pub fn memory(&self) -> &[Tryte] {
+        &self.memory
+    }
+
+    /// Returns main memory for modification, e.g. by a debugger.
+    pub fn memory_mut(&mut self) -> &mut [Tryte] {
+        &mut self.memory
+    }
+
+    /// Returns the halt code once the program has halted.
+    pub fn halt_code(&self) -> Option<i64> {
+        self.halt_code
+    }
 
-            let trit = Self::byte_to_trit(*byte)?;
-            
-            // Write the trit to the current Tryte in memory
-            self.memory[current_tryte_idx][current_trit_in_tryte] = trit;
+    /// Returns the data access made by the last instruction executed, if it made one.
+    pub fn last_access(&self) -> Option<&MemoryAccess> {
+        self.last_access.as_ref()
+    }
 
-            current_trit_in_tryte += 1;
+    /// Fetches and decodes the instruction at the PC without executing it.
+    pub fn current_instruction(&self) -> Result<Instruction, String> {
+        decode_instruction(&self.fetch()?).map_err(|e| e.to_string())
+    }
 
-            if current_trit_in_tryte == trits_per_tryte {
-                current_tryte_idx += 1;
-                current_trit_in_tryte = 0;
+    /// Runs the main fetch-decode-execute cycle until the program halts.
+    /// Returns the halt code, or an error if `max_steps` instructions retire first.
+    pub fn run(&mut self, max_steps: Option<u64>) -> Result<i64, String> {
+        let mut steps: u64 = 0;
+        while self.step()? {
+            steps += 1;
+            if max_steps.is_some_and(|limit| steps >= limit) {
+                return Err(format!("Step limit of {} instructions reached without HALT", steps));
             }
         }
-
-        println!("Successfully loaded {} Trytes into memory.", current_tryte_idx);
-        Ok(())
+        Ok(self.halt_code.unwrap_or(0))
     }
 
-    /// Creates a new, initialized CPU instance.
-    /// Creates a new, initialized CPU instance.
-    pub fn new() -> Self {
-        println!("Initializing btern CPU...");
-        Self {
-            // R0 is not special-cased here, but in the instruction logic.
-            // All registers default to a word of Zeros.
-            gpr: [[Trit::Z; 27]; 27],
-            pc: [Trit::Z; 27],
-            memory: vec![[Trit::Z; 9]; MEMORY_TRYTES], // Trit::Z is imported from btern_core
+    /// Like `run`, but writes a trace record (see btern_core::trace) for every
+    /// retired instruction to `trace`, one JSON object per line.
+    pub fn run_traced(&mut self, max_steps: Option<u64>, trace: &mut dyn Write) -> Result<i64, String> {
+        let mut steps: u64 = 0;
+        loop {
+            let (running, record) = self.trace_step(steps + 1)?;
+            writeln!(trace, "{}", record.to_json()).map_err(|e| format!("Error writing trace: {}", e))?;
+            if !running {
+                break;
+            }
+            steps += 1;
+            if max_steps.is_some_and(|limit| steps >= limit) {
+                return Err(format!("Step limit of {} instructions reached without HALT", steps));
+            }
         }
+        trace.flush().map_err(|e| format!("Error writing trace: {}", e))?;
+        Ok(self.halt_code.unwrap_or(0))
     }
 
-    /// Runs the main fetch-decode-execute cycle.
-    pub fn run(&mut self) -> Result<(), String> {
-        let mut running = true;
-        while running {
-            // 1. Fetch
-            let instruction_word = self.fetch()?;
-            
-            // 2. Decode
-            let instruction = self.decode(&instruction_word)?;
+    /// Performs a single cycle like `step`, and describes it as trace record number `step`.
+    pub fn trace_step(&mut self, step: u64) -> Result<(bool, TraceRecord), String> {
+        let pc = self.pc();
+        let instruction = self.current_instruction()?;
+        let reads = instruction
+            .source_registers()
+            .into_iter()
+            .map(|register| RegisterRead {
+                register,
+                value: word_to_i64(&self.gpr[register]),
+            })
+            .collect();
+        let before = self.gpr;
+
+        let running = self.step()?;
+
+        let writes = instruction
+            .destination_register()
+            .filter(|&register| register != 0)
+            .map(|register| RegisterWrite {
+                register,
+                old: word_to_i64(&before[register]),
+                new: word_to_i64(&self.gpr[register]),
+            })
+            .into_iter()
+            .collect();
+        let tryte_values = |trytes: &[Tryte]| trytes.iter().map(|t| trits_to_i64(t)).collect();
+        let memory = self.last_access.as_ref().map(|access| MemoryEffect {
+            write: access.kind == AccessKind::Write,
+            address: access.address as i64,
+            old: tryte_values(&access.old),
+            new: tryte_values(&access.new),
+        });
+
+        let record = TraceRecord {
+            step,
+            pc,
+            instruction,
+            reads,
+            writes,
+            memory,
+        };
+        Ok((running, record))
+    }
 
-            // 3. Execute
-            running = self.execute(&instruction)?;
+    /// Performs a single fetch-decode-execute cycle. Returns true if the CPU should continue running.
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.last_access = None;
 
-            // For now, we manually halt if we hit NOP after one cycle.
-            if instruction.opcode == Opcode::NOP {
-                running = false;
-            }
+        // 1. Fetch
+        let instruction_word = self.fetch()?;
+
+        // 2. Decode
+        let instruction = decode_instruction(&instruction_word).map_err(|e| e.to_string())?;
+
+        // 3. Execute
+        let running = self.execute(&instruction)?;
+
+        // For now, we manually halt if we hit NOP after one cycle.
+        if instruction.opcode == Opcode::NOP {
+            self.halt_code = Some(0);
+            return Ok(false);
         }
-        Ok(())
+        Ok(running)
     }
 
     /// Fetches a Word (3 trytes) from memory at the address in the PC.
@@ -126,54 +259,6 @@ impl Cpu {
         Ok(instruction_word)
     }
 
-    /// Decodes a 27-trit instruction Word into an Instruction struct.
-    fn decode(&self, instruction_word: &Word) -> Result<Instruction, String> {
-        // Opcode: 6 trits (21..26)
-        let opcode_val = trits_to_i64(&instruction_word[21..27]);
-        
-        // Rd: 3 trits (18..20)
-        let rd_val = trits_to_i64(&instruction_word[18..21]);
-        
-        // Rs1: 3 trits (15..17)
-        let rs1_val = trits_to_i64(&instruction_word[15..18]);
-        
-        // Rs2: 3 trits (12..14)
-        let rs2_val = trits_to_i64(&instruction_word[12..15]);
-        
-        // Imm/Offset: 12 trits (0..11)
-        let imm_val = trits_to_i64(&instruction_word[0..12]);
-
-        // Validate register indices (0 to 26)
-        if rd_val < 0 || rd_val > 26 || rs1_val < 0 || rs1_val > 26 || rs2_val < 0 || rs2_val > 26 {
-            return Err(format!("Invalid register index detected during decode: Rd={}, Rs1={}, Rs2={}", rd_val, rs1_val, rs2_val));
-        }
-
-        // Convert opcode integer to Opcode enum
-        let opcode = match opcode_val as u8 {
-            0 => Opcode::NOP,
-            1 => Opcode::ADD,
-            2 => Opcode::ADDI,
-            3 => Opcode::SUB,
-            4 => Opcode::SUBI,
-            5 => Opcode::LDW,
-            6 => Opcode::STW,
-            7 => Opcode::JMP,
-            8 => Opcode::CALL,
-            9 => Opcode::RET,
-            10 => Opcode::BRZ,
-            63 => Opcode::HALT,
-            _ => return Err(format!("Unknown opcode: {}", opcode_val)),
-        };
-
-        Ok(Instruction {
-            opcode,
-            rd: rd_val as usize,
-            rs1: rs1_val as usize,
-            rs2: rs2_val as usize,
-            imm: imm_val,
-        })
-    }
-
     /// Executes a decoded instruction. Returns true if the CPU should continue running.
     fn execute(&mut self, instruction: &Instruction) -> Result<bool, String> {
         match instruction.opcode {
@@ -182,7 +267,7 @@ impl Cpu {
                 Ok(true)
             }
             Opcode::HALT => {
-                self.print_register_state();
+                self.halt_code = Some(word_to_i64(&self.gpr[instruction.rs1]));
                 Ok(false)
             }
             Opcode::ADD => {
@@ -205,6 +290,67 @@ impl Cpu {
                 self.pc = self.next_pc();
                 Ok(true)
             }
+            Opcode::MUL => {
+                self.op_mul(instruction.rd, instruction.rs1, instruction.rs2);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::DIV => {
+                self.op_div(instruction.rd, instruction.rs1, instruction.rs2)?;
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::NEG => {
+                self.op_neg(instruction.rd, instruction.rs1);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::ABS => {
+                self.op_abs(instruction.rd, instruction.rs1);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::MIN => {
+                self.op_min(instruction.rd, instruction.rs1, instruction.rs2);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::MAX => {
+                self.op_max(instruction.rd, instruction.rs1, instruction.rs2);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::CONS => {
+                self.op_cons(instruction.rd, instruction.rs1, instruction.rs2);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::INV => {
+                // INV is an alias for NEG: tritwise inversion is arithmetic negation.
+                self.op_neg(instruction.rd, instruction.rs1);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::SHL => {
+                self.op_shl(instruction.rd, instruction.rs1, instruction.imm);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::SHR => {
+                self.op_shr(instruction.rd, instruction.rs1, instruction.imm);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::ROTL => {
+                self.op_rotl(instruction.rd, instruction.rs1, instruction.imm);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::ROTR => {
+                self.op_rotr(instruction.rd, instruction.rs1, instruction.imm);
+                self.pc = self.next_pc();
+                Ok(true)
+            }
             Opcode::LDW => {
                 self.op_ldw(instruction.rd, instruction.rs1, instruction.imm)?;
                 self.pc = self.next_pc();
@@ -215,6 +361,16 @@ impl Cpu {
                 self.pc = self.next_pc();
                 Ok(true)
             }
+            Opcode::LDT => {
+                self.op_ldt(instruction.rd, instruction.rs1, instruction.imm)?;
+                self.pc = self.next_pc();
+                Ok(true)
+            }
+            Opcode::STT => {
+                self.op_stt(instruction.rs1, instruction.imm, instruction.rs2)?;
+                self.pc = self.next_pc();
+                Ok(true)
+            }
             Opcode::JMP => {
                 self.op_jmp(instruction.imm);
                 Ok(true)
@@ -231,6 +387,14 @@ impl Cpu {
                 self.op_brz(instruction.rs1, instruction.imm);
                 Ok(true)
             }
+            Opcode::BRP => {
+                self.op_brp(instruction.rs1, instruction.imm);
+                Ok(true)
+            }
+            Opcode::BRN => {
+                self.op_brn(instruction.rs1, instruction.imm);
+                Ok(true)
+            }
         }
     }
 
@@ -300,10 +464,123 @@ impl Cpu {
         self.gpr[rd_idx] = result;
     }
 
+    /// Executes the MUL instruction. Rd = Rs1 * Rs2 (wraps to 27 trits).
+    pub fn op_mul(&mut self, rd_idx: usize, rs1_idx: usize, rs2_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = mul_words(&self.gpr[rs1_idx], &self.gpr[rs2_idx]);
+    }
+
+    /// Executes the DIV instruction. Rd = Rs1 / Rs2, rounded to the nearest integer.
+    /// Division by zero is a fault and stops execution.
+    pub fn op_div(&mut self, rd_idx: usize, rs1_idx: usize, rs2_idx: usize) -> Result<(), String> {
+        let (quotient, _remainder) = div_words(&self.gpr[rs1_idx], &self.gpr[rs2_idx])
+            .ok_or_else(|| format!("Division by zero (R{} / R{} with R{} = 0)", rs1_idx, rs2_idx, rs2_idx))?;
+
+        if rd_idx != 0 {
+            self.gpr[rd_idx] = quotient;
+        }
+        Ok(())
+    }
+
+    /// Executes the NEG instruction. Rd = -Rs1 (tritwise inversion).
+    pub fn op_neg(&mut self, rd_idx: usize, rs1_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = neg_word(&self.gpr[rs1_idx]);
+    }
+
+    /// Executes the ABS instruction. Rd = |Rs1|.
+    pub fn op_abs(&mut self, rd_idx: usize, rs1_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = abs_word(&self.gpr[rs1_idx]);
+    }
+
+    // --- Logical Operations ---
+
+    /// Executes the MIN instruction. Rd[i] = min(Rs1[i], Rs2[i]).
+    pub fn op_min(&mut self, rd_idx: usize, rs1_idx: usize, rs2_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = min_words(&self.gpr[rs1_idx], &self.gpr[rs2_idx]);
+    }
+
+    /// Executes the MAX instruction. Rd[i] = max(Rs1[i], Rs2[i]).
+    pub fn op_max(&mut self, rd_idx: usize, rs1_idx: usize, rs2_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = max_words(&self.gpr[rs1_idx], &self.gpr[rs2_idx]);
+    }
+
+    /// Executes the CONS instruction. Rd[i] = consensus(Rs1[i], Rs2[i]).
+    pub fn op_cons(&mut self, rd_idx: usize, rs1_idx: usize, rs2_idx: usize) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = consensus_words(&self.gpr[rs1_idx], &self.gpr[rs2_idx]);
+    }
+
+    // --- Shift/Rotate Operations ---
+
+    /// Executes the SHL instruction. Rd = Rs1 * 3^Imm.
+    /// A negative shift amount shifts right instead.
+    pub fn op_shl(&mut self, rd_idx: usize, rs1_idx: usize, imm: i64) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        let count = imm.unsigned_abs() as usize;
+        let rs1 = &self.gpr[rs1_idx];
+        self.gpr[rd_idx] = if imm >= 0 { shl_word(rs1, count) } else { shr_word(rs1, count) };
+    }
+
+    /// Executes the SHR instruction. Rd = Rs1 / 3^Imm (truncation, which equals rounding).
+    /// A negative shift amount shifts left instead.
+    pub fn op_shr(&mut self, rd_idx: usize, rs1_idx: usize, imm: i64) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        let count = imm.unsigned_abs() as usize;
+        let rs1 = &self.gpr[rs1_idx];
+        self.gpr[rd_idx] = if imm >= 0 { shr_word(rs1, count) } else { shl_word(rs1, count) };
+    }
+
+    /// Executes the ROTL instruction. Rd = Rs1 rotated left by Imm trits (modulo 27).
+    pub fn op_rotl(&mut self, rd_idx: usize, rs1_idx: usize, imm: i64) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = rotl_word(&self.gpr[rs1_idx], imm.rem_euclid(27) as usize);
+    }
+
+    /// Executes the ROTR instruction. Rd = Rs1 rotated right by Imm trits (modulo 27).
+    pub fn op_rotr(&mut self, rd_idx: usize, rs1_idx: usize, imm: i64) {
+        if rd_idx == 0 {
+            return;
+        }
+
+        self.gpr[rd_idx] = rotr_word(&self.gpr[rs1_idx], imm.rem_euclid(27) as usize);
+    }
+
     // --- Memory Access Operations ---
 
-    /// Calculates the effective Tryte address (EA = Rs1 + Imm) and validates it.
-    fn calculate_effective_address(&self, rs1_idx: usize, imm: i64) -> Result<usize, String> {
+    /// Calculates the effective Tryte address (EA = Rs1 + Imm) and validates it
+    /// for an access spanning `width` Trytes.
+    fn calculate_effective_address(&self, rs1_idx: usize, imm: i64, width: usize) -> Result<usize, String> {
         let rs1_value = word_to_i64(&self.gpr[rs1_idx]);
         let effective_address_value = rs1_value + imm;
 
@@ -313,22 +590,35 @@ impl Cpu {
 
         let ea = effective_address_value as usize;
 
-        // Check bounds for a 3-tryte Word access
-        if ea + 2 >= self.memory.len() {
+        // Check bounds for the whole access (3 Trytes for a Word, 1 for a Tryte)
+        if ea + width > self.memory.len() {
             return Err(format!("Memory access out of bounds at EA={}", ea));
         }
 
         Ok(ea)
     }
 
+    /// Records a data access of the `width` Trytes at `ea`, given their contents before it.
+    fn record_access(&mut self, kind: AccessKind, ea: usize, width: usize, old: Vec<Tryte>) {
+        self.last_access = Some(MemoryAccess {
+            kind,
+            address: ea,
+            old,
+            new: self.memory[ea..ea + width].to_vec(),
+        });
+    }
+
     /// Executes the LDW instruction. Rd = Mem[Rs1 + Offset].
     pub fn op_ldw(&mut self, rd_idx: usize, rs1_idx: usize, offset: i64) -> Result<(), String> {
+        // The load is performed (and can fault) even for R0; only the write is discarded.
+        let ea = self.calculate_effective_address(rs1_idx, offset, TRYTES_PER_WORD)?;
+        let old = self.memory[ea..ea + TRYTES_PER_WORD].to_vec();
+        self.record_access(AccessKind::Read, ea, TRYTES_PER_WORD, old);
+
         if rd_idx == 0 {
             return Ok(()); // Write to R0 is discarded
         }
 
-        let ea = self.calculate_effective_address(rs1_idx, offset)?;
-
         let mut loaded_word = [Trit::Z; 27];
         
         // Load 3 Trytes (1 Word)
@@ -342,13 +632,43 @@ impl Cpu {
 
     /// Executes the STW instruction. Mem[Rs1 + Offset] = Rs2.
     pub fn op_stw(&mut self, rs1_idx: usize, offset: i64, rs2_idx: usize) -> Result<(), String> {
-        let ea = self.calculate_effective_address(rs1_idx, offset)?;
+        let ea = self.calculate_effective_address(rs1_idx, offset, TRYTES_PER_WORD)?;
         let data_word = self.gpr[rs2_idx];
+        let old = self.memory[ea..ea + TRYTES_PER_WORD].to_vec();
 
         // Store 3 Trytes (1 Word)
         self.memory[ea].copy_from_slice(&data_word[0..9]);
         self.memory[ea + 1].copy_from_slice(&data_word[9..18]);
         self.memory[ea + 2].copy_from_slice(&data_word[18..27]);
+        self.record_access(AccessKind::Write, ea, TRYTES_PER_WORD, old);
+
+        Ok(())
+    }
+
+    /// Executes the LDT instruction. Rd = Mem[Rs1 + Offset] (one Tryte).
+    /// Sign extension is free in balanced ternary: the upper 18 trits are simply zero.
+    pub fn op_ldt(&mut self, rd_idx: usize, rs1_idx: usize, offset: i64) -> Result<(), String> {
+        // As for LDW, only the write to R0 is discarded, not the access.
+        let ea = self.calculate_effective_address(rs1_idx, offset, 1)?;
+        self.record_access(AccessKind::Read, ea, 1, vec![self.memory[ea]]);
+
+        if rd_idx == 0 {
+            return Ok(()); // Write to R0 is discarded
+        }
+
+        let mut loaded_word = [Trit::Z; 27];
+        loaded_word[0..9].copy_from_slice(&self.memory[ea]);
+
+        self.gpr[rd_idx] = loaded_word;
+        Ok(())
+    }
+
+    /// Executes the STT instruction. Mem[Rs1 + Offset] = lower 9 trits of Rs2.
+    pub fn op_stt(&mut self, rs1_idx: usize, offset: i64, rs2_idx: usize) -> Result<(), String> {
+        let ea = self.calculate_effective_address(rs1_idx, offset, 1)?;
+        let old = vec![self.memory[ea]];
+        self.memory[ea].copy_from_slice(&self.gpr[rs2_idx][0..9]);
+        self.record_access(AccessKind::Write, ea, 1, old);
 
         Ok(())
     }
@@ -391,15 +711,42 @@ impl Cpu {
         }
     }
 
-    /// Prints the state of the general-purpose registers (R0-R26).
+    /// BRP: Branch if Rs1 > 0.
+    pub fn op_brp(&mut self, rs1_idx: usize, offset: i64) {
+        // The sign of a balanced ternary value is its most significant non-zero trit.
+        if sign_word(&self.gpr[rs1_idx]) == Trit::P {
+            self.op_jmp(offset);
+        } else {
+            self.pc = self.next_pc();
+        }
+    }
+
+    /// BRN: Branch if Rs1 < 0.
+    pub fn op_brn(&mut self, rs1_idx: usize, offset: i64) {
+        if sign_word(&self.gpr[rs1_idx]) == Trit::N {
+            self.op_jmp(offset);
+        } else {
+            self.pc = self.next_pc();
+        }
+    }
+
+    /// Prints the state of the general-purpose registers (R0-R26) and the PC.
     pub fn print_register_state(&self) {
         println!("\n--- Register State ---");
         for i in 0..27 {
-            let val_i64 = word_to_i64(&self.gpr[i]);
-            let val_trits: String = self.gpr[i].iter().map(|t| t.to_string()).collect();
-            
-            println!("R{:02}: {:<27} ({})", i, val_trits, val_i64);
+            println!("R{:02}: {}", i, describe_word(&self.gpr[i]));
         }
+        println!("PC : {}", describe_word(&self.pc));
         println!("----------------------");
     }
+}
+
+/// Formats a Word as its trits (most significant first), decimal value and heptavintimal digits.
+pub fn describe_word(word: &Word) -> String {
+    format!(
+        "{} ({}) 0h{}",
+        trits_to_string(word),
+        word_to_i64(word),
+        trits_to_heptavintimal(word)
+    )
 }
\ No newline at end of file
@@ -0,0 +1,921 @@
+// debugger.rs - The interactive debugger (`bemu --debug`).
+//
+// Instead of running the program straight through, bemu reads commands from
+// stdin and executes them against the loaded CPU:
+//
+//     step [N]             (s)  execute N instructions (default 1)
+//     next [N]             (n)  like step, but run a CALL through to its return
+//     continue             (c)  run until a breakpoint or HALT
+//     break [LOCATION]     (b)  set a breakpoint; list breakpoints without an argument
+//     watch LOCATION [N]        stop when N trytes (default 1) are written
+//     rwatch LOCATION [N]       stop when N trytes are read
+//     awatch LOCATION [N]       stop when N trytes are read or written
+//     watch REG                 stop when a register changes value
+//     delete [N]           (d)  delete breakpoint or watchpoint N, or all of them
+//     regs                 (r)  show every register
+//     print REG            (p)  show one register (R0-R26, AT, SP, LR or PC)
+//     set REG VALUE             change a register (setting PC moves execution)
+//     mem ADDR [N]         (x)  show N trytes of memory (default 9)
+//     words ADDR [N]            show N words of memory (default 4)
+//     poke ADDR VALUE [tryte]   write a word (or a single tryte) to memory
+//     disas [ADDR [N]]          disassemble N instructions (default: around the PC)
+//     help                 (h)  list the commands
+//     quit                 (q)  leave the debugger
+//
+// Values and addresses are decimal, `0t` trits, `0h` heptavintimal, or a symbol
+// from the executable with an optional `+N` / `-N` offset. An empty line repeats
+// the previous command.
+//
+// Breakpoints and watchpoints may end with a condition, `when A OP B`, where A
+// and B are registers or values and OP is one of < <= > >= == !=. A conditional
+// breakpoint or watchpoint only stops when the condition holds; `watch when R3 < 0`
+// on its own stops as soon as the condition becomes true. Conditions compare the
+// 27-trit Words with btern_core arithmetic, as the CPU would.
+//
+// Watchpoints see the data accesses of LDW, STW, LDT and STT (not instruction
+// fetches), which is how a stray store into the program's own code is caught.
+
+use crate::cpu::{describe_word, AccessKind, Cpu};
+use btern_core::exe::{Executable, Symbol};
+use btern_core::{
+    compare_words, decode_instruction, i64_to_tryte, i64_to_word, trits_from_heptavintimal, trits_from_str,
+    trits_to_heptavintimal, trits_to_i64, trits_to_string, trytes_to_word, word_to_i64, word_to_trytes, Format, Opcode,
+    Word, REG_AT, REG_LR, REG_SP, TRYTES_PER_WORD, TRYTE_MAX, WORD_MAX,
+};
+use std::cmp::{Ordering, Reverse};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  step [N]             (s)  execute N instructions (default 1)
+  next [N]             (n)  like step, but run a CALL through to its return
+  continue             (c)  run until a breakpoint or HALT
+  break [LOCATION]     (b)  set a breakpoint; list breakpoints without an argument
+  watch LOCATION [N]        stop when N trytes (default 1) are written
+  rwatch LOCATION [N]       stop when N trytes are read
+  awatch LOCATION [N]       stop when N trytes are read or written
+  watch REG                 stop when a register changes value
+  delete [N]           (d)  delete breakpoint or watchpoint N, or all of them
+  regs                 (r)  show every register
+  print REG            (p)  show one register (R0-R26, AT, SP, LR or PC)
+  set REG VALUE             change a register (setting PC moves execution)
+  mem ADDR [N]         (x)  show N trytes of memory (default 9)
+  words ADDR [N]            show N words of memory (default 4)
+  poke ADDR VALUE [tryte]   write a word (or a single tryte) to memory
+  disas [ADDR [N]]          disassemble N instructions (default: around the PC)
+  help                 (h)  show this list
+  quit                 (q)  leave the debugger
+Values are decimal, 0t trits, 0h heptavintimal, or a symbol with an optional +N/-N.
+Breakpoints and watchpoints take an optional condition: 'when A OP B' with A and B
+registers or values and OP one of < <= > >= == !=. 'watch when R3 < 0' stops as
+soon as the condition becomes true.";
+
+/// Registers with a conventional name, besides their number.
+const REGISTER_ALIASES: [(&str, usize); 3] = [("AT", REG_AT), ("SP", REG_SP), ("LR", REG_LR)];
+
+/// Comparison operators of a `when` condition. Two-character operators come first
+/// so that `<=` is not read as `<`.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+/// Why execution returned control to the user.
+enum Stop {
+    /// The requested number of instructions ran.
+    Done,
+    /// The PC reached breakpoint N.
+    Breakpoint(usize),
+    /// Watchpoints fired, each with its number and the reason.
+    Watchpoints(Vec<(usize, String)>),
+    /// The program executed HALT with this code.
+    Halted(i64),
+    /// The CPU raised a runtime fault.
+    Fault(String),
+}
+
+/// A register operand of `print` and `set`.
+#[derive(Copy, Clone)]
+enum Register {
+    Gpr(usize),
+    Pc,
+}
+
+/// Which data accesses a memory watchpoint stops on.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            Watch::Read => kind == AccessKind::Read,
+            Watch::Write => kind == AccessKind::Write,
+            Watch::Access => true,
+        }
+    }
+
+    fn command(self) -> &'static str {
+        match self {
+            Watch::Read => "rwatch",
+            Watch::Write => "watch",
+            Watch::Access => "awatch",
+        }
+    }
+}
+
+/// What makes a breakpoint or watchpoint stop execution.
+enum Trigger {
+    /// The PC reaches an address (a breakpoint).
+    Address(i64),
+    /// A data access touches any of the `len` trytes from `start`.
+    Memory { start: i64, len: i64, watch: Watch },
+    /// A GPR changes value; `last` is its value after the previous instruction.
+    Register { index: usize, last: Word },
+    /// Only the condition: stops when it changes from false to true.
+    Condition { was_true: bool },
+}
+
+#[derive(Copy, Clone)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    fn test(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Lt => ordering.is_lt(),
+            Comparison::Le => ordering.is_le(),
+            Comparison::Gt => ordering.is_gt(),
+            Comparison::Ge => ordering.is_ge(),
+            Comparison::Eq => ordering.is_eq(),
+            Comparison::Ne => ordering.is_ne(),
+        }
+    }
+}
+
+/// One side of a condition.
+enum Operand {
+    Register(Register),
+    Value(Word),
+}
+
+/// A `when A OP B` condition.
+struct Condition {
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+    /// The condition as written, for listings.
+    text: String,
+}
+
+/// A breakpoint or watchpoint. Both share one numbering, as in `delete N`.
+struct Breakpoint {
+    id: usize,
+    trigger: Trigger,
+    condition: Option<Condition>,
+}
+
+/// Debugger state: the CPU being debugged, the program's symbols and the breakpoints.
+pub struct Debugger<'a> {
+    cpu: &'a mut Cpu,
+    /// Symbols sorted by address, for naming locations.
+    symbols: Vec<Symbol>,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: usize,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(cpu: &'a mut Cpu, exe: &Executable) -> Self {
+        let mut symbols = exe.symbols.clone();
+        symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+        Debugger {
+            cpu,
+            symbols,
+            breakpoints: Vec::new(),
+            next_breakpoint: 1,
+        }
+    }
+
+    /// Reads and executes commands from stdin until `quit` or end of input.
+    /// Returns the program's halt code, or 0 if it did not halt.
+    pub fn run(&mut self) -> i64 {
+        println!("bemu debugger. Type 'help' for a list of commands.");
+        println!("{}", self.current_line());
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut previous = String::new();
+        loop {
+            print!("(bemu) ");
+            io::stdout().flush().ok();
+            let Some(Ok(line)) = lines.next() else {
+                println!();
+                break;
+            };
+            let line = if line.trim().is_empty() { previous.clone() } else { line };
+            previous = line.clone();
+
+            match self.execute(&line) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+        self.cpu.halt_code().unwrap_or(0)
+    }
+
+    /// Executes one command line. Returns true if the debugger should exit.
+    fn execute(&mut self, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(false);
+        };
+
+        match command {
+            "step" | "s" => {
+                let count = self.count_arg(args, 1)?;
+                let stop = self.step(count, false)?;
+                self.report(stop);
+            }
+            "next" | "n" => {
+                let count = self.count_arg(args, 1)?;
+                let stop = self.step(count, true)?;
+                self.report(stop);
+            }
+            "continue" | "c" => {
+                expect_args(args, 0, 0)?;
+                let stop = self.resume()?;
+                self.report(stop);
+            }
+            "break" | "b" => {
+                let (args, condition) = self.split_condition(args)?;
+                expect_args(args, 0, 1)?;
+                match (args.first(), condition) {
+                    (Some(location), condition) => {
+                        let address = self.parse_value(location)?;
+                        self.add_breakpoint(Trigger::Address(address), condition);
+                    }
+                    (None, Some(_)) => {
+                        return Err("break needs a location; use 'watch when ...' to stop on a condition".to_string())
+                    }
+                    (None, None) => self.list_breakpoints(),
+                }
+            }
+            "watch" | "rwatch" | "awatch" => {
+                let watch = match command {
+                    "rwatch" => Watch::Read,
+                    "awatch" => Watch::Access,
+                    _ => Watch::Write,
+                };
+                let (args, condition) = self.split_condition(args)?;
+                expect_args(args, 0, 2)?;
+                match self.watch_trigger(watch, args, condition.is_some())? {
+                    Some(trigger) => self.add_breakpoint(trigger, condition),
+                    None => self.list_breakpoints(),
+                }
+            }
+            "delete" | "d" => {
+                expect_args(args, 0, 1)?;
+                self.delete_breakpoint(args.first().copied())?;
+            }
+            "regs" | "r" => {
+                expect_args(args, 0, 0)?;
+                self.cpu.print_register_state();
+            }
+            "print" | "p" => {
+                expect_args(args, 1, 1)?;
+                let register = parse_register(args[0])?;
+                println!("{}: {}", register_name(register), describe_word(&self.read_register(register)));
+            }
+            "set" => {
+                expect_args(args, 2, 2)?;
+                let register = parse_register(args[0])?;
+                let value = i64_to_word(self.parse_value(args[1])?);
+                match register {
+                    Register::Gpr(0) => return Err("R0 is hardwired to zero".to_string()),
+                    Register::Gpr(index) => self.cpu.set_register(index, value),
+                    Register::Pc => self.cpu.set_pc(word_to_i64(&value)),
+                }
+                self.sync_watchpoints();
+                println!("{}: {}", register_name(register), describe_word(&self.read_register(register)));
+            }
+            "mem" | "x" => {
+                expect_args(args, 1, 2)?;
+                let address = self.parse_value(args[0])?;
+                let count = self.count_arg(&args[1..], 9)?;
+                self.show_trytes(address, count)?;
+            }
+            "words" => {
+                expect_args(args, 1, 2)?;
+                let address = self.parse_value(args[0])?;
+                let count = self.count_arg(&args[1..], 4)?;
+                self.show_words(address, count)?;
+            }
+            "poke" => {
+                expect_args(args, 2, 3)?;
+                let address = self.parse_value(args[0])?;
+                let value = self.parse_value(args[1])?;
+                match args.get(2).copied() {
+                    None | Some("word") => self.poke_word(address, value)?,
+                    Some("tryte") => self.poke_tryte(address, value)?,
+                    Some(width) => return Err(format!("unknown width '{}' (expected 'word' or 'tryte')", width)),
+                }
+                self.sync_watchpoints();
+            }
+            "disas" => {
+                expect_args(args, 0, 2)?;
+                let (start, count) = match args.first() {
+                    Some(address) => (self.parse_value(address)?, self.count_arg(&args[1..], 8)?),
+                    // A few instructions before the PC and a few after.
+                    None => ((self.cpu.pc() - 3 * TRYTES_PER_WORD as i64).max(0), 8),
+                };
+                for index in 0..count {
+                    let address = start + (index * TRYTES_PER_WORD) as i64;
+                    if !self.in_memory(address, TRYTES_PER_WORD) {
+                        break;
+                    }
+                    println!("{}", self.disassembly_line(address));
+                }
+            }
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(true),
+            _ => return Err(format!("unknown command '{}' (try 'help')", command)),
+        }
+        Ok(false)
+    }
+
+    // --- Execution ---
+
+    /// Executes `count` instructions. With `over_calls`, a CALL counts as one
+    /// instruction: the subroutine runs until it returns past the CALL.
+    fn step(&mut self, count: usize, over_calls: bool) -> Result<Stop, String> {
+        self.check_running()?;
+        for index in 0..count {
+            let call = over_calls && matches!(self.cpu.current_instruction(), Ok(inst) if inst.opcode == Opcode::CALL);
+            if call {
+                if let Some(stop) = self.run_over_call() {
+                    return Ok(stop);
+                }
+            } else if let Some(stop) = self.single_step() {
+                return Ok(stop);
+            }
+            if index + 1 < count {
+                if let Some(id) = self.breakpoint_hit(self.cpu.pc()) {
+                    return Ok(Stop::Breakpoint(id));
+                }
+            }
+        }
+        Ok(Stop::Done)
+    }
+
+    /// Runs the CALL at the PC until control comes back to the instruction after it.
+    /// A recursive call returns to the same address in a deeper frame, so the stack
+    /// pointer must also be back at (or above) its value at the CALL.
+    fn run_over_call(&mut self) -> Option<Stop> {
+        let return_address = self.cpu.pc() + TRYTES_PER_WORD as i64;
+        let sp = word_to_i64(&self.cpu.register(REG_SP));
+        loop {
+            if let Some(stop) = self.single_step() {
+                return Some(stop);
+            }
+            let pc = self.cpu.pc();
+            if pc == return_address && word_to_i64(&self.cpu.register(REG_SP)) >= sp {
+                return None;
+            }
+            if let Some(id) = self.breakpoint_hit(pc) {
+                return Some(Stop::Breakpoint(id));
+            }
+        }
+    }
+
+    /// Runs until a breakpoint, a watchpoint, HALT or a fault. A breakpoint at the starting PC
+    /// does not stop execution again.
+    fn resume(&mut self) -> Result<Stop, String> {
+        self.check_running()?;
+        loop {
+            if let Some(stop) = self.single_step() {
+                return Ok(stop);
+            }
+            if let Some(id) = self.breakpoint_hit(self.cpu.pc()) {
+                return Ok(Stop::Breakpoint(id));
+            }
+        }
+    }
+
+    /// Executes one instruction. Returns why execution must stop, if it must.
+    fn single_step(&mut self) -> Option<Stop> {
+        let from = self.cpu.pc();
+        let instruction = match self.cpu.current_instruction() {
+            Ok(inst) => inst.to_string(),
+            Err(_) => "?".to_string(),
+        };
+        match self.cpu.step() {
+            Ok(true) => self.check_watchpoints(&format!("{} at {}", instruction, self.location(from))),
+            Ok(false) => Some(Stop::Halted(self.cpu.halt_code().unwrap_or(0))),
+            Err(e) => Some(Stop::Fault(e)),
+        }
+    }
+
+    fn check_running(&self) -> Result<(), String> {
+        match self.cpu.halt_code() {
+            Some(code) => Err(format!("the program has halted (code {}); there is nothing to run", code)),
+            None => Ok(()),
+        }
+    }
+
+    /// Prints why execution stopped and where.
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(id) => println!("Breakpoint {} at {}", id, self.location(self.cpu.pc())),
+            Stop::Watchpoints(fired) => {
+                for (id, reason) in fired {
+                    println!("Watchpoint {}: {}", id, reason);
+                }
+            }
+            Stop::Halted(code) => {
+                println!("Program halted with code {}.", code);
+                return;
+            }
+            Stop::Fault(e) => println!("Fault: {}", e),
+        }
+        println!("{}", self.current_line());
+    }
+
+    // --- Breakpoints and watchpoints ---
+
+    fn add_breakpoint(&mut self, trigger: Trigger, condition: Option<Condition>) {
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            trigger,
+            condition,
+        });
+        self.sync_watchpoints();
+        let breakpoint = &self.breakpoints[self.breakpoints.len() - 1];
+        let kind = if matches!(breakpoint.trigger, Trigger::Address(_)) { "Breakpoint" } else { "Watchpoint" };
+        println!("{} {}: {}", kind, id, self.describe_breakpoint(breakpoint));
+    }
+
+    /// Builds the trigger for `watch`, `rwatch` or `awatch` with `args` (the condition
+    /// already removed). Returns None if the command should list the watchpoints instead.
+    fn watch_trigger(&self, watch: Watch, args: &[&str], conditional: bool) -> Result<Option<Trigger>, String> {
+        let Some(&first) = args.first() else {
+            if !conditional {
+                return Ok(None);
+            }
+            if watch != Watch::Write {
+                return Err(format!("{} needs a location to watch", watch.command()));
+            }
+            return Ok(Some(Trigger::Condition { was_true: false }));
+        };
+
+        if let Ok(register) = parse_register(first) {
+            let index = match register {
+                Register::Pc => {
+                    return Err("the PC changes on every instruction; use 'break' to stop at an address".to_string())
+                }
+                Register::Gpr(index) => index,
+            };
+            if watch != Watch::Write {
+                return Err("registers can only be watched for changes; use 'watch'".to_string());
+            }
+            if args.len() > 1 {
+                return Err("a register watchpoint takes no length".to_string());
+            }
+            return Ok(Some(Trigger::Register {
+                index,
+                last: self.cpu.register(index),
+            }));
+        }
+
+        let start = self.parse_value(first)?;
+        let len = self.count_arg(&args[1..], 1)?;
+        self.check_range(start, len)?;
+        Ok(Some(Trigger::Memory {
+            start,
+            len: len as i64,
+            watch,
+        }))
+    }
+
+    fn delete_breakpoint(&mut self, id: Option<&str>) -> Result<(), String> {
+        let Some(id) = id else {
+            self.breakpoints.clear();
+            println!("Deleted all breakpoints and watchpoints.");
+            return Ok(());
+        };
+        let id: usize = id.parse().map_err(|_| format!("invalid breakpoint number '{}'", id))?;
+        let index = self
+            .breakpoints
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or_else(|| format!("no breakpoint or watchpoint number {}", id))?;
+        self.breakpoints.remove(index);
+        println!("Deleted {}.", id);
+        Ok(())
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+        }
+        for breakpoint in &self.breakpoints {
+            println!("{:>3}  {}", breakpoint.id, self.describe_breakpoint(breakpoint));
+        }
+    }
+
+    fn describe_breakpoint(&self, breakpoint: &Breakpoint) -> String {
+        let mut text = match &breakpoint.trigger {
+            Trigger::Address(address) => format!("break {}", self.location(*address)),
+            Trigger::Memory { start, len: 1, watch } => format!("{} {}", watch.command(), self.location(*start)),
+            Trigger::Memory { start, len, watch } => {
+                format!("{} {} ({} trytes)", watch.command(), self.location(*start), len)
+            }
+            Trigger::Register { index, .. } => format!("watch R{}", index),
+            Trigger::Condition { .. } => "watch".to_string(),
+        };
+        if let Some(condition) = &breakpoint.condition {
+            text.push_str(&format!(" when {}", condition.text));
+        }
+        text
+    }
+
+    /// Returns the breakpoint that stops execution at `address`: one whose condition,
+    /// if it has one, holds.
+    fn breakpoint_hit(&self, address: i64) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .find(|b| matches!(b.trigger, Trigger::Address(at) if at == address) && self.condition_holds(b))
+            .map(|b| b.id)
+    }
+
+    fn has_breakpoint(&self, address: i64) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|b| matches!(b.trigger, Trigger::Address(at) if at == address))
+    }
+
+    fn condition_holds(&self, breakpoint: &Breakpoint) -> bool {
+        breakpoint.condition.as_ref().is_none_or(|c| self.evaluate(c))
+    }
+
+    fn evaluate(&self, condition: &Condition) -> bool {
+        let value = |operand: &Operand| match operand {
+            Operand::Register(register) => self.read_register(*register),
+            Operand::Value(word) => *word,
+        };
+        condition
+            .comparison
+            .test(compare_words(&value(&condition.left), &value(&condition.right)))
+    }
+
+    /// Checks the watchpoints after an instruction (described by `executed`) has run,
+    /// and updates the register values and conditions they track.
+    /// Returns every watchpoint that fires.
+    fn check_watchpoints(&mut self, executed: &str) -> Option<Stop> {
+        let access = self.cpu.last_access().cloned();
+        let accessed = access.as_ref().map(|a| self.location(a.address as i64));
+        let registers: Vec<Word> = (0..27).map(|i| self.cpu.register(i)).collect();
+        let holds: Vec<bool> = self.breakpoints.iter().map(|b| self.condition_holds(b)).collect();
+
+        let mut fired = Vec::new();
+        for (breakpoint, holds) in self.breakpoints.iter_mut().zip(holds) {
+            let reason = match &mut breakpoint.trigger {
+                Trigger::Address(_) => None,
+                Trigger::Memory { start, len, watch } => access
+                    .as_ref()
+                    .filter(|a| {
+                        let (first, end) = (a.address as i64, (a.address + a.new.len()) as i64);
+                        watch.matches(a.kind) && first < *start + *len && *start < end
+                    })
+                    .map(|a| {
+                        let (old, new) = (trytes_to_word(&a.old), trytes_to_word(&a.new));
+                        let width = if a.new.len() == 1 { "tryte" } else { "word" };
+                        let place = accessed.as_deref().unwrap_or("");
+                        match a.kind {
+                            AccessKind::Read => format!(
+                                "{} read from {} by {}\n  value: {}",
+                                width,
+                                place,
+                                executed,
+                                describe_word(&old)
+                            ),
+                            AccessKind::Write => format!(
+                                "{} written to {} by {}\n  old: {}\n  new: {}",
+                                width,
+                                place,
+                                executed,
+                                describe_word(&old),
+                                describe_word(&new)
+                            ),
+                        }
+                    }),
+                Trigger::Register { index, last } => {
+                    let old = std::mem::replace(last, registers[*index]);
+                    (old != *last).then(|| {
+                        format!(
+                            "R{} changed by {}\n  old: {}\n  new: {}",
+                            index,
+                            executed,
+                            describe_word(&old),
+                            describe_word(last)
+                        )
+                    })
+                }
+                Trigger::Condition { was_true } => {
+                    let became_true = holds && !*was_true;
+                    *was_true = holds;
+                    let text = breakpoint.condition.as_ref().map_or("", |c| c.text.as_str());
+                    became_true.then(|| format!("{} became true after {}", text, executed))
+                }
+            };
+            if let Some(reason) = reason {
+                if holds {
+                    fired.push((breakpoint.id, reason));
+                }
+            }
+        }
+        (!fired.is_empty()).then_some(Stop::Watchpoints(fired))
+    }
+
+    /// Brings the values watchpoints compare against up to date, so that changes made
+    /// from the prompt (or before the watchpoint existed) do not fire them.
+    fn sync_watchpoints(&mut self) {
+        let registers: Vec<Word> = (0..27).map(|i| self.cpu.register(i)).collect();
+        let holds: Vec<bool> = self.breakpoints.iter().map(|b| self.condition_holds(b)).collect();
+        for (breakpoint, holds) in self.breakpoints.iter_mut().zip(holds) {
+            match &mut breakpoint.trigger {
+                Trigger::Register { index, last } => *last = registers[*index],
+                Trigger::Condition { was_true } => *was_true = holds,
+                Trigger::Address(_) | Trigger::Memory { .. } => {}
+            }
+        }
+    }
+
+    // --- Registers and memory ---
+
+    fn read_register(&self, register: Register) -> Word {
+        match register {
+            Register::Gpr(index) => self.cpu.register(index),
+            Register::Pc => i64_to_word(self.cpu.pc()),
+        }
+    }
+
+    fn in_memory(&self, address: i64, width: usize) -> bool {
+        address >= 0 && address as usize + width <= self.cpu.memory().len()
+    }
+
+    fn check_range(&self, address: i64, width: usize) -> Result<usize, String> {
+        if !self.in_memory(address, width) {
+            return Err(format!(
+                "address {} is outside memory (0..{} trytes)",
+                address,
+                self.cpu.memory().len()
+            ));
+        }
+        Ok(address as usize)
+    }
+
+    fn show_trytes(&self, address: i64, count: usize) -> Result<(), String> {
+        let start = self.check_range(address, count.max(1))?;
+        for (offset, tryte) in self.cpu.memory()[start..start + count].iter().enumerate() {
+            let address = (start + offset) as i64;
+            let row = format!(
+                "{:05}  {}  {:>6}  0h{}  {}",
+                address,
+                trits_to_string(tryte),
+                trits_to_i64(tryte),
+                trits_to_heptavintimal(tryte),
+                self.label_at(address)
+            );
+            println!("{}", row.trim_end());
+        }
+        Ok(())
+    }
+
+    fn show_words(&self, address: i64, count: usize) -> Result<(), String> {
+        let start = self.check_range(address, count.max(1) * TRYTES_PER_WORD)?;
+        for index in 0..count {
+            let at = start + index * TRYTES_PER_WORD;
+            let word = trytes_to_word(&self.cpu.memory()[at..at + TRYTES_PER_WORD]);
+            let row = format!("{:05}  {}  {}", at, describe_word(&word), self.label_at(at as i64));
+            println!("{}", row.trim_end());
+        }
+        Ok(())
+    }
+
+    fn poke_word(&mut self, address: i64, value: i64) -> Result<(), String> {
+        let start = self.check_range(address, TRYTES_PER_WORD)?;
+        let trytes = word_to_trytes(&i64_to_word(value));
+        self.cpu.memory_mut()[start..start + TRYTES_PER_WORD].copy_from_slice(&trytes);
+        self.show_words(address, 1)
+    }
+
+    fn poke_tryte(&mut self, address: i64, value: i64) -> Result<(), String> {
+        if !(-TRYTE_MAX..=TRYTE_MAX).contains(&value) {
+            return Err(format!("{} does not fit in a Tryte ({}..{})", value, -TRYTE_MAX, TRYTE_MAX));
+        }
+        let start = self.check_range(address, 1)?;
+        self.cpu.memory_mut()[start] = i64_to_tryte(value);
+        self.show_trytes(address, 1)
+    }
+
+    // --- Locations and disassembly ---
+
+    /// Names `address` by the nearest symbol at or below it, e.g. `00012 <fact+3>`.
+    fn location(&self, address: i64) -> String {
+        match self.symbols.iter().rev().find(|s| s.address <= address) {
+            Some(symbol) if symbol.address == address => format!("{:05} <{}>", address, symbol.name),
+            Some(symbol) => format!("{:05} <{}+{}>", address, symbol.name, address - symbol.address),
+            None => format!("{:05}", address),
+        }
+    }
+
+    /// Returns the names of the symbols at exactly `address`, as a label column.
+    fn label_at(&self, address: i64) -> String {
+        let names: Vec<&str> = self
+            .symbols
+            .iter()
+            .filter(|s| s.address == address)
+            .map(|s| s.name.as_str())
+            .collect();
+        if names.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", names.join(", "))
+        }
+    }
+
+    /// The instruction at the PC, marked as the current one.
+    fn current_line(&self) -> String {
+        let pc = self.cpu.pc();
+        if !self.in_memory(pc, TRYTES_PER_WORD) {
+            return format!("=> {:05}  (outside memory)", pc);
+        }
+        self.disassembly_line(pc)
+    }
+
+    /// Disassembles the Word at `address`. The PC is marked `=>` and breakpoints `*`;
+    /// jump and branch targets are shown as locations.
+    fn disassembly_line(&self, address: i64) -> String {
+        let marker = match (address == self.cpu.pc(), self.has_breakpoint(address)) {
+            (true, true) => "=>*",
+            (true, false) => "=> ",
+            (false, true) => "  *",
+            (false, false) => "   ",
+        };
+        let start = address as usize;
+        let word = trytes_to_word(&self.cpu.memory()[start..start + TRYTES_PER_WORD]);
+        let text = match decode_instruction(&word) {
+            Ok(inst) => match inst.opcode.format() {
+                Format::Jump | Format::Branch => {
+                    format!("{:<20} ; -> {}", inst.to_string(), self.location(address + inst.imm))
+                }
+                _ => inst.to_string(),
+            },
+            Err(_) => format!(".word {}", word_to_i64(&word)),
+        };
+        format!("{} {:<24} 0h{}  {}", marker, self.location(address), trits_to_heptavintimal(&word), text)
+    }
+
+    // --- Operands ---
+
+    /// Parses a number, or a symbol with an optional `+N` / `-N` offset.
+    fn parse_value(&self, text: &str) -> Result<i64, String> {
+        // Symbols cannot start with a digit, so such text is a number or an error.
+        let number = parse_number(text);
+        if number.is_ok() || text.trim_start_matches(['+', '-']).starts_with(|c: char| c.is_ascii_digit()) {
+            return number;
+        }
+        let (name, offset) = match text.find(['+', '-']) {
+            Some(index) if index > 0 => (&text[..index], parse_number(&text[index..])?),
+            _ => (text, 0),
+        };
+        let symbol = self
+            .symbols
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| format!("'{}' is not a number or a known symbol", text))?;
+        Some(symbol.address + offset)
+            .filter(|value| (-WORD_MAX..=WORD_MAX).contains(value))
+            .ok_or_else(|| format!("'{}' does not fit in a Word (27 trits)", text))
+    }
+
+    /// Splits a trailing `when A OP B` condition off a command's arguments.
+    fn split_condition<'b>(&self, args: &'b [&'b str]) -> Result<(&'b [&'b str], Option<Condition>), String> {
+        let Some(position) = args.iter().position(|&arg| arg == "when") else {
+            return Ok((args, None));
+        };
+        let condition = self.parse_condition(&args[position + 1..].join(" "))?;
+        Ok((&args[..position], Some(condition)))
+    }
+
+    /// Parses `A OP B`, where A and B are registers or values.
+    fn parse_condition(&self, text: &str) -> Result<Condition, String> {
+        let (position, symbol, comparison) = COMPARISONS
+            .iter()
+            .filter_map(|&(symbol, comparison)| text.find(symbol).map(|p| (p, symbol, comparison)))
+            .min_by_key(|&(p, symbol, _)| (p, Reverse(symbol.len())))
+            .ok_or_else(|| format!("expected a comparison like 'R3 < 0', found '{}'", text))?;
+        let operand = |text: &str| -> Result<Operand, String> {
+            let text = text.trim();
+            if text.is_empty() {
+                return Err("a condition needs a value on each side of the comparison".to_string());
+            }
+            match parse_register(text) {
+                Ok(register) => Ok(Operand::Register(register)),
+                Err(_) => Ok(Operand::Value(i64_to_word(self.parse_value(text)?))),
+            }
+        };
+        Ok(Condition {
+            left: operand(&text[..position])?,
+            comparison,
+            right: operand(&text[position + symbol.len()..])?,
+            text: text.to_string(),
+        })
+    }
+
+    /// Parses an optional count argument, which must be positive.
+    fn count_arg(&self, args: &[&str], default: usize) -> Result<usize, String> {
+        let Some(text) = args.first() else {
+            return Ok(default);
+        };
+        match self.parse_value(text)? {
+            count if count > 0 => Ok(count as usize),
+            count => Err(format!("count must be positive, found {}", count)),
+        }
+    }
+}
+
+/// Checks that a command received between `min` and `max` arguments.
+fn expect_args(args: &[&str], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        return Err(format!("expected {} argument(s), found {}", expected, args.len()));
+    }
+    Ok(())
+}
+
+/// Parses a decimal, `0t` (trits, most significant first) or `0h` (heptavintimal) number,
+/// with an optional sign. The value must fit in a Word, and trit and heptavintimal
+/// literals may be at most a Word long (27 trits or 9 digits).
+fn parse_number(text: &str) -> Result<i64, String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let trits = if let Some(trits) = digits.strip_prefix("0t") {
+        Some(trits_from_str(trits))
+    } else {
+        digits.strip_prefix("0h").map(trits_from_heptavintimal)
+    };
+    let value = match trits {
+        Some(Ok(trits)) if trits.len() > 27 => {
+            return Err(format!("'{}' does not fit in a Word (27 trits)", text))
+        }
+        Some(Ok(trits)) => trits_to_i64(&trits),
+        Some(Err(e)) => return Err(e.to_string()),
+        None => digits.parse::<i64>().map_err(|_| format!("invalid number '{}'", text))?,
+    };
+    let value = if negative { value.checked_neg() } else { Some(value) };
+    value
+        .filter(|value| (-WORD_MAX..=WORD_MAX).contains(value))
+        .ok_or_else(|| format!("'{}' does not fit in a Word (27 trits)", text))
+}
+
+/// Parses a register name: R0-R26, AT, SP, LR or PC (case-insensitive).
+fn parse_register(text: &str) -> Result<Register, String> {
+    let upper = text.to_ascii_uppercase();
+    if upper == "PC" {
+        return Ok(Register::Pc);
+    }
+    if let Some(&(_, index)) = REGISTER_ALIASES.iter().find(|(name, _)| *name == upper) {
+        return Ok(Register::Gpr(index));
+    }
+    match upper.strip_prefix('R').and_then(|n| n.parse::<usize>().ok()) {
+        Some(index) if index < 27 => Ok(Register::Gpr(index)),
+        _ => Err(format!("unknown register '{}' (expected R0-R26, AT, SP, LR or PC)", text)),
+    }
+}
+
+fn register_name(register: Register) -> String {
+    match register {
+        Register::Gpr(index) => format!("R{}", index),
+        Register::Pc => "PC".to_string(),
+    }
+}
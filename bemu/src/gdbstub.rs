@@ -0,0 +1,574 @@
+// gdbstub.rs - A GDB Remote Serial Protocol stub (`bemu --gdb`).
+//
+// bemu waits for a debugger on a TCP socket (`--gdb 1234` listens on 127.0.0.1,
+// `--gdb host:port` on any address) or speaks the protocol over stdin/stdout
+// (`--gdb -`, for `target remote | bemu prog.bin --gdb -`). Supported packets:
+//
+//     ?                 last stop reason
+//     g / G             read / write all registers
+//     p n / P n=v       read / write register n
+//     m a,l / M a,l:xx  read / write memory
+//     Z0,a,k / z0,a,k   insert / remove a software breakpoint
+//     s [a] / c [a]     single-step / continue (optionally from address a)
+//     qSupported, qXfer:features:read:target.xml, QStartNoAckMode, k, D
+//
+// The protocol is byte-oriented, so trit-wide values are mapped as follows:
+//
+// * Registers: 28 registers, each 8 bytes little-endian, two's complement.
+//   Numbers 0-26 are R0-R26 holding their integer value (a Word fits easily in
+//   an i64: |value| <= 3812798742493); number 27 is the PC as a *byte* address,
+//   twice the tryte address, so that `$pc` can be used with the memory mapping
+//   below. Writes outside the Word range, or an odd PC, are rejected.
+// * Memory: tryte n occupies bytes 2n and 2n+1, holding its value (-9841..9841)
+//   as an i16, little-endian. A write must leave every tryte it touches within
+//   that range, or nothing is written.
+// * Breakpoints are byte addresses too, so they must be even.
+//
+// A HALT is reported as process exit (`Wxx`, the halt code modulo 256). Faults
+// are reported as signals: SIGILL for an undecodable instruction, SIGFPE for
+// DIV by zero and SIGSEGV for bad memory accesses. The debugger's input is read
+// on its own thread, so an interrupt (Ctrl-C in gdb, the byte 0x03) stops a
+// running `c` after the current instruction with SIGINT.
+
+use crate::cpu::Cpu;
+use btern_core::{i64_to_tryte, i64_to_word, trits_to_i64, word_to_i64, Opcode, TRYTE_MAX, WORD_MAX};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Number of registers in the `g` packet: R0-R26 and the PC.
+const REGISTER_COUNT: usize = 28;
+
+/// Register number of the PC.
+const PC_REGISTER: usize = 27;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// Byte the debugger sends to interrupt a running program.
+const INTERRUPT: u8 = 0x03;
+
+/// How a debugging session ended.
+pub enum Outcome {
+    /// The program halted with this code while the debugger was attached.
+    Exited(i64),
+    /// The debugger detached; the program should run on by itself.
+    Detached,
+    /// The debugger killed the program or closed the connection.
+    Killed,
+}
+
+/// Waits for a debugger on `address` (`-` for stdin/stdout, a port, or host:port)
+/// and serves it until the session ends.
+pub fn serve(cpu: &mut Cpu, address: &str, quiet: bool) -> Result<Outcome, String> {
+    if address == "-" {
+        let stdout = io::stdout();
+        return GdbStub::new(cpu, io::stdin(), stdout.lock()).run();
+    }
+
+    let address = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("127.0.0.1:{}", address)
+    };
+    let listener = TcpListener::bind(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    if !quiet {
+        println!("Waiting for a GDB connection on {}...", address);
+    }
+    let (stream, peer) = listener
+        .accept()
+        .map_err(|e| format!("Cannot accept a connection on {}: {}", address, e))?;
+    if !quiet {
+        println!("Debugger connected from {}.", peer);
+    }
+    let reader = stream.try_clone().map_err(|e| e.to_string())?;
+    GdbStub::new(cpu, reader, stream).run()
+}
+
+/// The debugger's side of the connection, read on a separate thread so that the
+/// stub can look for an interrupt between instructions without blocking.
+struct Input {
+    chunks: Receiver<Vec<u8>>,
+    buffer: VecDeque<u8>,
+}
+
+impl Input {
+    fn spawn(mut reader: impl Read + Send + 'static) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut chunk = [0u8; 4096];
+            // Ends with the input, or once the stub has gone away.
+            while let Ok(count) = reader.read(&mut chunk) {
+                if count == 0 || sender.send(chunk[..count].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Input {
+            chunks,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Returns true, consuming the byte, if the debugger has sent an interrupt.
+    fn interrupted(&mut self) -> bool {
+        while let Ok(chunk) = self.chunks.try_recv() {
+            self.buffer.extend(chunk);
+        }
+        match self.buffer.iter().position(|&b| b == INTERRUPT) {
+            Some(index) => {
+                self.buffer.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for Input {
+    /// Waits for more input if none is buffered; an empty slice means the input has ended.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buffer.is_empty() {
+            if let Ok(chunk) = self.chunks.recv() {
+                self.buffer.extend(chunk);
+            }
+        }
+        Ok(self.buffer.make_contiguous())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.buffer.drain(..amount);
+    }
+}
+
+struct GdbStub<'a, W> {
+    cpu: &'a mut Cpu,
+    reader: Input,
+    writer: W,
+    /// Breakpoints, as tryte addresses.
+    breakpoints: BTreeSet<i64>,
+    /// False once the debugger has sent QStartNoAckMode.
+    acks: bool,
+}
+
+impl<'a, W: Write> GdbStub<'a, W> {
+    fn new(cpu: &'a mut Cpu, reader: impl Read + Send + 'static, writer: W) -> Self {
+        GdbStub {
+            cpu,
+            reader: Input::spawn(reader),
+            writer,
+            breakpoints: BTreeSet::new(),
+            acks: true,
+        }
+    }
+
+    fn run(&mut self) -> Result<Outcome, String> {
+        loop {
+            let Some(packet) = self.receive().map_err(|e| e.to_string())? else {
+                return Ok(Outcome::Killed);
+            };
+            let (reply, outcome) = self.handle(&packet);
+            // `k` has no reply.
+            if packet != "k" {
+                self.send(&reply).map_err(|e| e.to_string())?;
+            }
+            // The OK is still acknowledged; acks stop after it.
+            if packet == "QStartNoAckMode" {
+                self.acks = false;
+            }
+            if let Some(outcome) = outcome {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    // --- Framing ---
+
+    /// Reads the next `$data#cs` packet, acknowledging it unless acks are off.
+    /// Stray acknowledgements and interrupt bytes between packets are ignored.
+    /// Returns None when the connection closes.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0u8];
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0u8; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if expected != Some(actual) && self.acks {
+                self.writer.write_all(b"-")?;
+                self.writer.flush()?;
+                continue;
+            }
+            if self.acks {
+                // Flushed now: after `c` the reply may be a long time coming.
+                self.writer.write_all(b"+")?;
+                self.writer.flush()?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+    }
+
+    /// Sends `data` as a packet. With acks on, the debugger's `+` is read back
+    /// (and a `-` answered by sending again).
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let escaped = escape(data.as_bytes());
+        let checksum = escaped.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut packet = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        loop {
+            self.writer.write_all(&packet)?;
+            self.writer.flush()?;
+            if !self.acks {
+                return Ok(());
+            }
+            let mut ack = [0u8];
+            if self.reader.read(&mut ack)? == 0 || ack[0] != b'-' {
+                return Ok(());
+            }
+        }
+    }
+
+    // --- Packets ---
+
+    /// Handles one packet. Returns the reply and, if the session is over, how it ended.
+    fn handle(&mut self, packet: &str) -> (String, Option<Outcome>) {
+        let ok = |result: Result<(), u8>| match result {
+            Ok(()) => "OK".to_string(),
+            Err(errno) => format!("E{:02x}", errno),
+        };
+        let reply = |result: Result<String, u8>| result.unwrap_or_else(|errno| format!("E{:02x}", errno));
+
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTER_COUNT).map(|n| encode_register(self.read_register(n))).collect(),
+            "G" => ok(self.write_registers(args)),
+            "p" => reply(parse_hex(args).map(|n| encode_register(self.read_register(n as usize)))),
+            "P" => ok(self.write_register(args)),
+            "m" => reply(self.read_memory(args)),
+            "M" => ok(self.write_memory(args)),
+            "Z" | "z" => match args.strip_prefix("0,") {
+                Some(args) => ok(self.set_breakpoint(args, command == "Z")),
+                // Hardware breakpoints and watchpoints are not supported.
+                None => String::new(),
+            },
+            "s" | "c" => {
+                if let Err(errno) = self.jump(args) {
+                    format!("E{:02x}", errno)
+                } else {
+                    return self.resume(command == "s");
+                }
+            }
+            "H" | "T" => "OK".to_string(),
+            "k" => return (String::new(), Some(Outcome::Killed)),
+            "D" => return ("OK".to_string(), Some(Outcome::Detached)),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        (reply, None)
+    }
+
+    /// Answers general queries; unsupported ones get the empty reply.
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if packet == "QStartNoAckMode" {
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return xfer(&target_xml(), range);
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // --- Registers ---
+
+    fn read_register(&self, number: usize) -> i64 {
+        match number {
+            PC_REGISTER => 2 * self.cpu.pc(),
+            n if n < PC_REGISTER => word_to_i64(&self.cpu.register(n)),
+            // Unknown registers read as zero, as gdb expects for `p`.
+            _ => 0,
+        }
+    }
+
+    /// Checks a register value and applies it.
+    fn set_register(&mut self, number: usize, value: i64) -> Result<(), u8> {
+        if !(-WORD_MAX..=WORD_MAX).contains(&value) {
+            return Err(22);
+        }
+        match number {
+            PC_REGISTER if value % 2 != 0 => return Err(22),
+            PC_REGISTER => self.cpu.set_pc(value / 2),
+            n if n < PC_REGISTER => self.cpu.set_register(n, i64_to_word(value)),
+            _ => return Err(22),
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, hex: &str) -> Result<(), u8> {
+        if hex.len() != REGISTER_COUNT * 16 {
+            return Err(22);
+        }
+        let values: Vec<i64> = (0..REGISTER_COUNT)
+            .map(|n| decode_register(&hex[n * 16..(n + 1) * 16]))
+            .collect::<Result<_, _>>()?;
+        // Validate everything before changing anything.
+        if values.iter().any(|v| !(-WORD_MAX..=WORD_MAX).contains(v)) || values[PC_REGISTER] % 2 != 0 {
+            return Err(22);
+        }
+        for (number, value) in values.into_iter().enumerate() {
+            self.set_register(number, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_register(&mut self, args: &str) -> Result<(), u8> {
+        let (number, value) = args.split_once('=').ok_or(22)?;
+        self.set_register(parse_hex(number)? as usize, decode_register(value)?)
+    }
+
+    // --- Memory ---
+
+    /// Parses `addr,length` (hex) and checks that the bytes are backed by memory.
+    fn byte_range(&self, args: &str) -> Result<(usize, usize), u8> {
+        let (address, length) = args.split_once(',').ok_or(22)?;
+        let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+        if address.checked_add(length).is_none_or(|end| end > 2 * self.cpu.memory().len() as u64) {
+            return Err(14);
+        }
+        Ok((address as usize, length as usize))
+    }
+
+    fn tryte_bytes(&self, tryte: usize) -> [u8; 2] {
+        (trits_to_i64(&self.cpu.memory()[tryte]) as i16).to_le_bytes()
+    }
+
+    fn read_memory(&self, args: &str) -> Result<String, u8> {
+        let (address, length) = self.byte_range(args)?;
+        Ok((address..address + length)
+            .map(|byte| format!("{:02x}", self.tryte_bytes(byte / 2)[byte % 2]))
+            .collect())
+    }
+
+    fn write_memory(&mut self, args: &str) -> Result<(), u8> {
+        let (range, data) = args.split_once(':').ok_or(22)?;
+        let (address, length) = self.byte_range(range)?;
+        let bytes = decode_hex(data)?;
+        if bytes.len() != length {
+            return Err(22);
+        }
+        if length == 0 {
+            return Ok(());
+        }
+
+        // Merge the new bytes into each tryte they touch, then check every result
+        // before storing any of them.
+        let first = address / 2;
+        let last = (address + length - 1) / 2;
+        let mut values = Vec::new();
+        for tryte in first..=last {
+            let mut pair = self.tryte_bytes(tryte);
+            for (half, byte) in pair.iter_mut().enumerate() {
+                let at = 2 * tryte + half;
+                if (address..address + length).contains(&at) {
+                    *byte = bytes[at - address];
+                }
+            }
+            let value = i16::from_le_bytes(pair) as i64;
+            if value.abs() > TRYTE_MAX {
+                return Err(22);
+            }
+            values.push(value);
+        }
+        for (tryte, value) in (first..=last).zip(values) {
+            self.cpu.memory_mut()[tryte] = i64_to_tryte(value);
+        }
+        Ok(())
+    }
+
+    // --- Execution ---
+
+    /// Handles a `Z0`/`z0` packet's `addr,kind`.
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Result<(), u8> {
+        let address = parse_hex(args.split(',').next().unwrap_or(""))?;
+        if address % 2 != 0 {
+            return Err(22);
+        }
+        let tryte = (address / 2) as i64;
+        if insert {
+            self.breakpoints.insert(tryte);
+        } else {
+            self.breakpoints.remove(&tryte);
+        }
+        Ok(())
+    }
+
+    /// Moves the PC to the byte address given with `s` or `c`, if any.
+    fn jump(&mut self, address: &str) -> Result<(), u8> {
+        if address.is_empty() {
+            return Ok(());
+        }
+        let address = parse_hex(address)? as i64;
+        self.set_register(PC_REGISTER, address)
+    }
+
+    /// Executes one instruction, or runs until a breakpoint or an interrupt from
+    /// the debugger. Returns the stop reply.
+    fn resume(&mut self, single: bool) -> (String, Option<Outcome>) {
+        loop {
+            let instruction = self.cpu.current_instruction();
+            match self.cpu.step() {
+                Ok(true) => {
+                    if single || self.breakpoints.contains(&self.cpu.pc()) {
+                        return (format!("S{:02x}", SIGTRAP), None);
+                    }
+                    if self.reader.interrupted() {
+                        return (format!("S{:02x}", SIGINT), None);
+                    }
+                }
+                Ok(false) => {
+                    let code = self.cpu.halt_code().unwrap_or(0);
+                    return (format!("W{:02x}", code.rem_euclid(256)), Some(Outcome::Exited(code)));
+                }
+                Err(_) => {
+                    let signal = match instruction {
+                        Err(_) => SIGILL,
+                        Ok(inst) if inst.opcode == Opcode::DIV => SIGFPE,
+                        Ok(_) => SIGSEGV,
+                    };
+                    return (format!("S{:02x}", signal), None);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a register value as 8 little-endian bytes in hex.
+fn encode_register(value: i64) -> String {
+    value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes 8 little-endian bytes in hex.
+fn decode_register(hex: &str) -> Result<i64, u8> {
+    let bytes: [u8; 8] = decode_hex(hex)?.try_into().map_err(|_| 22)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn parse_hex(text: &str) -> Result<u64, u8> {
+    u64::from_str_radix(text, 16).map_err(|_| 22)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, u8> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(22);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).ok_or(22)?, 16).map_err(|_| 22))
+        .collect()
+}
+
+/// Escapes the bytes the protocol reserves (`#`, `$`, `}` and `*`).
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &b in data {
+        if matches!(b, b'#' | b'$' | b'}' | b'*') {
+            out.extend_from_slice(&[b'}', b ^ 0x20]);
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => out.extend(bytes.next().map(|&next| next ^ 0x20)),
+            _ => out.push(b),
+        }
+    }
+    out
+}
+
+/// The target description: the register layout documented at the top of this file.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  \
+         <feature name=\"org.btern.cpu\">\n",
+    );
+    for n in 0..REGISTER_COUNT {
+        let (name, kind) = match n {
+            24 => ("at".to_string(), "int64"),
+            25 => ("sp".to_string(), "int64"),
+            26 => ("lr".to_string(), "int64"),
+            PC_REGISTER => ("pc".to_string(), "code_ptr"),
+            n => (format!("r{}", n), "int64"),
+        };
+        xml.push_str(&format!(
+            "    <reg name=\"{}\" bitsize=\"64\" type=\"{}\" regnum=\"{}\"/>\n",
+            name, kind, n
+        ));
+    }
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+/// Answers a `qXfer` read of `offset,length` (hex) from `document`.
+fn xfer(document: &str, range: &str) -> String {
+    let Some((offset, length)) = range.split_once(',') else {
+        return "E16".to_string();
+    };
+    let (Ok(offset), Ok(length)) = (parse_hex(offset), parse_hex(length)) else {
+        return "E16".to_string();
+    };
+    let bytes = document.as_bytes();
+    let start = (offset as usize).min(bytes.len());
+    let end = start.saturating_add(length as usize).min(bytes.len());
+    let more = if end < bytes.len() { 'm' } else { 'l' };
+    format!("{}{}", more, String::from_utf8_lossy(&bytes[start..end]))
+}
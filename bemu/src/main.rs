@@ -1,40 +1,204 @@
 // main.rs - The entry point for the btern emulator (bemu).
+//
+// Exit status: when the guest executes `HALT Rs`, bemu exits with the value of Rs
+// (reduced modulo 256, as the OS only keeps the low 8 bits). A bare `HALT` exits
+// with 0. Load failures, runtime faults and hitting --max-steps exit with 1.
+// With --debug, the program runs under the interactive debugger instead (see
+// debugger.rs) and bemu exits with the halt code, or 0 if the program never halted.
+// With --gdb, bemu waits for a GDB remote protocol debugger (see gdbstub.rs); if it
+// detaches, the program runs on to completion, and if it kills the program bemu exits with 1.
+// With --trace, every retired instruction is also written to a JSON-lines file (see
+// btern_core::trace).
 
-use std::fs;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::process;
 
 // Declare the modules we'll be using.
 mod cpu;
+mod debugger;
+mod gdbstub;
 
-use cpu::Cpu;
+use cpu::{Cpu, MEMORY_TRYTES};
+use debugger::Debugger;
+use gdbstub::Outcome;
 
-const PROGRAM_FILE: &str = "test_program.bin";
+const USAGE: &str = "\
+Usage: bemu <program.bin> [options]
+
+Options:
+  --mem-size <TRYTES>   Size of main memory in Trytes (default: 19683)
+  --entry <ADDRESS>     Tryte address to start execution at (default: the image's entry point)
+  --max-steps <N>       Stop with an error after N instructions without HALT
+  --quiet               Suppress progress messages
+  --dump-regs           Print the register state on exit
+  --debug               Run under the interactive debugger (type 'help' at its prompt)
+  --gdb <ADDRESS>       Wait for a GDB remote protocol debugger on a TCP port (127.0.0.1),
+                        host:port, or '-' for stdin/stdout
+  --trace <FILE>        Write a JSON-lines record of every retired instruction to FILE";
+
+/// Command-line options accepted by bemu.
+struct Options {
+    program: String,
+    mem_size: usize,
+    entry: Option<i64>,
+    max_steps: Option<u64>,
+    quiet: bool,
+    dump_regs: bool,
+    debug: bool,
+    gdb: Option<String>,
+    trace: Option<String>,
+}
+
+/// Parses the value following an option, e.g. the `100` in `--max-steps 100`.
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", option))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, option))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        program: String::new(),
+        mem_size: MEMORY_TRYTES,
+        entry: None,
+        max_steps: None,
+        quiet: false,
+        dump_regs: false,
+        debug: false,
+        gdb: None,
+        trace: None,
+    };
+    let mut program = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--mem-size" => options.mem_size = parse_value(arg, iter.next())?,
+            "--entry" => options.entry = Some(parse_value(arg, iter.next())?),
+            "--max-steps" => options.max_steps = Some(parse_value(arg, iter.next())?),
+            "--quiet" | "-q" => options.quiet = true,
+            "--dump-regs" => options.dump_regs = true,
+            "--debug" => options.debug = true,
+            "--gdb" => options.gdb = Some(parse_value(arg, iter.next())?),
+            "--trace" => options.trace = Some(parse_value(arg, iter.next())?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+            _ if program.is_none() => program = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if options.mem_size == 0 {
+        return Err("--mem-size must be at least 1 Tryte".to_string());
+    }
+    if options.debug && options.gdb.is_some() {
+        return Err("--debug and --gdb cannot be used together".to_string());
+    }
+    if options.trace.is_some() && (options.debug || options.gdb.is_some()) {
+        return Err("--trace cannot be combined with --debug or --gdb".to_string());
+    }
+    // Over stdio, stdout carries the protocol.
+    if options.gdb.as_deref() == Some("-") {
+        options.quiet = true;
+    }
+    options.program = program.ok_or_else(|| USAGE.to_string())?;
+    Ok(options)
+}
 
 fn main() {
-    println!("Starting btern Virtual Machine (bemu)...");
-    
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if !options.quiet {
+        println!("Starting btern Virtual Machine (bemu)...");
+        println!("Initializing btern CPU...");
+    }
+
     // Create a new instance of our CPU.
-    let mut btern_cpu = Cpu::new();
+    let mut btern_cpu = Cpu::new(options.mem_size);
 
     // Load the program into memory.
-    let program_bytes = match fs::read(PROGRAM_FILE) {
+    let program_bytes = match fs::read(&options.program) {
         Ok(bytes) => bytes,
         Err(e) => {
-            eprintln!("Error reading program file {}: {}", PROGRAM_FILE, e);
-            std::process::exit(1);
+            eprintln!("Error reading program file {}: {}", options.program, e);
+            process::exit(1);
+        }
+    };
+
+    let exe = match btern_cpu.load_program(&program_bytes) {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("Error loading program: {}", e);
+            process::exit(1);
         }
     };
+    if !options.quiet {
+        println!(
+            "Successfully loaded {} Trytes in {} segment(s) into memory (entry point {}).",
+            exe.total_trytes(),
+            exe.segments.len(),
+            exe.entry
+        );
+    }
+    if let Some(entry) = options.entry {
+        btern_cpu.set_pc(entry);
+    }
 
-    if let Err(e) = btern_cpu.load_program(&program_bytes) {
-        eprintln!("Error loading program: {}", e);
-        std::process::exit(1);
+    if options.debug {
+        let halt_code = Debugger::new(&mut btern_cpu, &exe).run();
+        if options.dump_regs {
+            btern_cpu.print_register_state();
+        }
+        process::exit(halt_code.rem_euclid(256) as i32);
+    }
+    if let Some(address) = &options.gdb {
+        match gdbstub::serve(&mut btern_cpu, address, options.quiet) {
+            Ok(Outcome::Exited(halt_code)) => process::exit(halt_code.rem_euclid(256) as i32),
+            Ok(Outcome::Killed) => process::exit(1),
+            // Run the rest of the program as usual.
+            Ok(Outcome::Detached) => {}
+            Err(e) => {
+                eprintln!("GDB stub error: {}", e);
+                process::exit(1);
+            }
+        }
     }
 
     // Run the simulation.
-    match btern_cpu.run() {
-        Ok(_) => println!("\nbemu simulation finished successfully."),
+    let result = match &options.trace {
+        Some(path) => match File::create(path) {
+            Ok(file) => btern_cpu.run_traced(options.max_steps, &mut BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Error creating trace file {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => btern_cpu.run(options.max_steps),
+    };
+    if options.dump_regs {
+        btern_cpu.print_register_state();
+    }
+
+    match result {
+        Ok(halt_code) => {
+            if !options.quiet {
+                println!("\nbemu simulation finished successfully (halt code {}).", halt_code);
+            }
+            process::exit(halt_code.rem_euclid(256) as i32);
+        }
         Err(e) => {
             eprintln!("\nAn error occurred during execution: {}", e);
-            std::process::exit(1);
+            process::exit(1);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,45 @@
+// Shared helpers for the bemu integration tests: small programs assembled by hand
+// with btern_core, written to temporary files for the bemu binary to load.
+
+#![allow(dead_code)]
+
+use btern_core::image::{write_image, ProgramImage};
+use btern_core::{encode_instruction, word_to_trytes, Instruction, Opcode, Tryte};
+use std::fs;
+use std::path::PathBuf;
+
+pub fn inst(opcode: Opcode, rd: usize, rs1: usize, rs2: usize, imm: i64) -> Instruction {
+    Instruction {
+        opcode,
+        rd,
+        rs1,
+        rs2,
+        imm,
+    }
+}
+
+/// Writes `program` as a flat image starting at 0 and returns its path.
+/// `name` keeps the files of concurrently running tests apart.
+pub fn write_program(name: &str, program: &[Instruction]) -> PathBuf {
+    let trytes: Vec<Tryte> = program
+        .iter()
+        .flat_map(|inst| word_to_trytes(&encode_instruction(inst)))
+        .collect();
+    let path = std::env::temp_dir().join(format!("bemu-test-{}-{}.bin", name, std::process::id()));
+    fs::write(&path, write_image(&ProgramImage { entry: 0, trytes })).unwrap();
+    path
+}
+
+/// examples/countdown.basm, assembled: R2 = 5 + 4 + 3 + 2 + 1.
+pub fn countdown() -> Vec<Instruction> {
+    vec![
+        inst(Opcode::ADDI, 1, 0, 0, 5),  // 00000        ADDI R1, R0, 5
+        inst(Opcode::BRZ, 0, 1, 0, 12),  // 00003 loop:  BRZ R1, done
+        inst(Opcode::CALL, 0, 0, 0, 12), // 00006        CALL accumulate
+        inst(Opcode::SUBI, 1, 1, 0, 1),  // 00009        SUBI R1, R1, 1
+        inst(Opcode::JMP, 0, 0, 0, -9),  // 00012        JMP loop
+        inst(Opcode::HALT, 0, 0, 0, 0),  // 00015 done:  HALT
+        inst(Opcode::ADD, 2, 2, 1, 0),   // 00018        ADD R2, R2, R1
+        inst(Opcode::RET, 0, 0, 0, 0),   // 00021        RET
+    ]
+}
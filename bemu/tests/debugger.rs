@@ -0,0 +1,80 @@
+// debugger.rs - Feeds commands to `bemu --debug` on stdin and checks its replies.
+
+mod common;
+
+use common::{countdown, write_program};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs the debugger on examples/countdown.basm with `commands`, returning stdout.
+fn debug(name: &str, commands: &str) -> String {
+    let path = write_program(name, &countdown());
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bemu"))
+        .arg(&path)
+        .arg("--debug")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(path).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn values_outside_a_word_or_tryte_are_rejected() {
+    let output = debug(
+        "ranges",
+        "set R1 99999999999999999\n\
+         set R1 -3812798742494\n\
+         set R1 0t++++++++++++++++++++++++++++\n\
+         poke 100 3812798742494\n\
+         poke 100 9842 tryte\n\
+         p R1\n\
+         x 100 1\n\
+         q\n",
+    );
+    for value in [
+        "99999999999999999",
+        "-3812798742494",
+        "0t++++++++++++++++++++++++++++",
+        "3812798742494",
+    ] {
+        assert!(
+            output.contains(&format!("Error: '{}' does not fit in a Word (27 trits)", value)),
+            "{} accepted:\n{}",
+            value,
+            output
+        );
+    }
+    assert!(
+        output.contains("Error: 9842 does not fit in a Tryte (-9841..9841)"),
+        "{}",
+        output
+    );
+    // Nothing was written.
+    assert!(output.contains("R1: 000000000000000000000000000 (0)"), "{}", output);
+    assert!(output.contains("00100  000000000       0"), "{}", output);
+}
+
+#[test]
+fn values_at_the_limits_are_accepted() {
+    let output = debug(
+        "limits",
+        "set R1 3812798742493\nset R2 -0hDDDDDDDDD\npoke 100 -9841 tryte\nq\n",
+    );
+    assert!(
+        output.contains("R1: +++++++++++++++++++++++++++ (3812798742493)"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("R2: --------------------------- (-3812798742493)"),
+        "{}",
+        output
+    );
+    assert!(output.contains("00100  ---------   -9841"), "{}", output);
+    assert!(!output.contains("Error"), "{}", output);
+}
@@ -0,0 +1,156 @@
+// gdbstub.rs - Drives `bemu --gdb -` with a scripted Remote Serial Protocol client.
+//
+// The client speaks the protocol over the child's stdin/stdout the way gdb does
+// (with acknowledgements), against examples/countdown.basm and a busy loop.
+
+mod common;
+
+use btern_core::{encode_instruction, trits_to_i64, word_to_trytes, Instruction, Opcode};
+use common::{countdown, inst, write_program};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+struct Client {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl Client {
+    fn start(program: &PathBuf) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bemu"))
+            .arg(program)
+            .args(["--gdb", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        Client { child, input, output }
+    }
+
+    /// Sends `data` as a packet and waits for the stub's acknowledgement.
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.input, "${}#{:02x}", data, checksum).unwrap();
+        self.input.flush().unwrap();
+        let mut ack = [0u8];
+        self.output.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+', "packet {} was not acknowledged", data);
+    }
+
+    /// Reads one reply packet, checks its checksum and acknowledges it.
+    fn reply(&mut self) -> String {
+        let mut skipped = Vec::new();
+        self.output.read_until(b'$', &mut skipped).unwrap();
+        let mut data = Vec::new();
+        self.output.read_until(b'#', &mut data).unwrap();
+        assert_eq!(data.pop(), Some(b'#'));
+        let mut checksum = [0u8; 2];
+        self.output.read_exact(&mut checksum).unwrap();
+        let expected = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), expected);
+        self.input.write_all(b"+").unwrap();
+        self.input.flush().unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+
+    /// Waits for bemu to exit and returns its exit status.
+    fn finish(mut self) -> i32 {
+        drop(self.input);
+        self.child.wait().unwrap().code().unwrap()
+    }
+}
+
+fn encode_register(value: i64) -> String {
+    value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_register(hex: &str) -> i64 {
+    let bytes: Vec<u8> = (0..16)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    i64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// The memory mapping of a Word: each tryte as a little-endian i16.
+fn word_bytes(inst: &Instruction) -> String {
+    word_to_trytes(&encode_instruction(inst))
+        .iter()
+        .flat_map(|tryte| (trits_to_i64(tryte) as i16).to_le_bytes())
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[test]
+fn registers_memory_breakpoints_and_stepping() {
+    let program = countdown();
+    let path = write_program("countdown", &program);
+    let mut client = Client::start(&path);
+
+    assert_eq!(client.request("?"), "S05");
+    let registers = client.request("g");
+    assert_eq!(registers.len(), 28 * 16);
+    assert!(
+        registers.chars().all(|c| c == '0'),
+        "registers start at zero: {}",
+        registers
+    );
+
+    // Memory: tryte n is bytes 2n and 2n+1.
+    assert_eq!(client.request("m0,6"), word_bytes(&program[0]));
+
+    // Run to `done` (tryte 15, byte 0x1e).
+    assert_eq!(client.request("Z0,1e,0"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p1b"), encode_register(0x1e));
+    let registers = client.request("g");
+    let value = |n: usize| decode_register(&registers[n * 16..(n + 1) * 16]);
+    assert_eq!((value(1), value(2)), (0, 15));
+
+    // Set R2 to 100 with G, keeping everything else.
+    let mut registers: Vec<String> = (0..28).map(|n| registers[n * 16..(n + 1) * 16].to_string()).collect();
+    registers[2] = encode_register(100);
+    assert_eq!(client.request(&format!("G{}", registers.concat())), "OK");
+    assert_eq!(client.request("p2"), encode_register(100));
+
+    // Replace `HALT` with `HALT R2`, so the exit status shows the new R2.
+    let halt = word_bytes(&inst(Opcode::HALT, 0, 2, 0, 0));
+    assert_eq!(client.request(&format!("M1e,6:{}", halt)), "OK");
+    assert_eq!(client.request("m1e,6"), halt);
+    assert_eq!(client.request("m20000,2"), "E0e");
+
+    assert_eq!(client.request("z0,1e,0"), "OK");
+    assert_eq!(client.request("s"), "W64");
+    assert_eq!(client.finish(), 100);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn interrupt_stops_a_running_program() {
+    let path = write_program("loop", &[inst(Opcode::JMP, 0, 0, 0, 0)]);
+    let mut client = Client::start(&path);
+
+    client.send("c");
+    thread::sleep(Duration::from_millis(100));
+    client.input.write_all(&[0x03]).unwrap();
+    client.input.flush().unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.request("p1b"), encode_register(0));
+
+    // `k` has no reply; bemu exits with 1.
+    client.send("k");
+    assert_eq!(client.finish(), 1);
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,112 @@
+// trace.rs - Runs `bemu --trace` on small programs and checks the records.
+
+mod common;
+
+use btern_core::trace::{parse_trace, MemoryEffect, RegisterRead, RegisterWrite, TraceRecord};
+use btern_core::{Instruction, Opcode};
+use common::{countdown, inst, write_program};
+use std::fs;
+use std::process::Command;
+
+/// Traces `program` with bemu and returns the records and bemu's exit status.
+fn trace(name: &str, program: &[Instruction]) -> (Vec<TraceRecord>, i32) {
+    let path = write_program(name, program);
+    let trace_path = path.with_extension("trace");
+    let status = Command::new(env!("CARGO_BIN_EXE_bemu"))
+        .arg(&path)
+        .arg("--quiet")
+        .arg("--trace")
+        .arg(&trace_path)
+        .status()
+        .unwrap();
+    let records = parse_trace(&fs::read_to_string(&trace_path).unwrap()).unwrap();
+    fs::remove_file(path).unwrap();
+    fs::remove_file(trace_path).unwrap();
+    (records, status.code().unwrap())
+}
+
+#[test]
+fn countdown_trace_records_every_retired_instruction() {
+    let program = countdown();
+    let (records, status) = trace("countdown", &program);
+    assert_eq!(status, 0);
+
+    // ADDI, five passes of BRZ/CALL/ADD/RET/SUBI/JMP, then the final BRZ and HALT.
+    assert_eq!(records.len(), 1 + 5 * 6 + 2);
+    for (index, record) in records.iter().enumerate() {
+        assert_eq!(record.step, index as u64 + 1);
+        assert_eq!(record.instruction, program[record.pc as usize / 3]);
+        assert_eq!(record.memory, None);
+    }
+
+    assert_eq!(records[0].reads, vec![RegisterRead { register: 0, value: 0 }]);
+    assert_eq!(
+        records[0].writes,
+        vec![RegisterWrite {
+            register: 1,
+            old: 0,
+            new: 5
+        }]
+    );
+    // CALL writes the return address to LR; RET reads it back.
+    let call = &records[2];
+    assert_eq!((call.pc, call.instruction.opcode), (6, Opcode::CALL));
+    assert_eq!(
+        call.writes,
+        vec![RegisterWrite {
+            register: 26,
+            old: 0,
+            new: 9
+        }]
+    );
+    let ret = &records[4];
+    assert_eq!(ret.reads, vec![RegisterRead { register: 26, value: 9 }]);
+    assert!(ret.writes.is_empty());
+
+    // The last ADD leaves R2 = 15, and the trace ends with the HALT.
+    let last_add = records
+        .iter()
+        .rev()
+        .find(|r| r.instruction.opcode == Opcode::ADD)
+        .unwrap();
+    assert_eq!(last_add.writes[0].new, 15);
+    assert_eq!(records.last().unwrap().pc, 15);
+}
+
+#[test]
+fn loads_and_stores_record_their_memory_access() {
+    let program = [
+        inst(Opcode::ADDI, 1, 0, 0, -9842), // R1 = -9842 = [9841, -1, 0] as trytes
+        inst(Opcode::STW, 0, 0, 1, 30),     // Mem[30..33] = R1
+        inst(Opcode::LDW, 2, 0, 0, 30),     // R2 = Mem[30..33]
+        inst(Opcode::STT, 0, 0, 1, 33),     // Mem[33] = lower tryte of R1
+        inst(Opcode::LDT, 0, 0, 0, 33),     // read into R0: no register write
+        inst(Opcode::HALT, 0, 0, 0, 0),
+    ];
+    let (records, status) = trace("memory", &program);
+    assert_eq!(status, 0);
+    assert_eq!(records.len(), 6);
+
+    let memory = |write, address, old: &[i64], new: &[i64]| {
+        Some(MemoryEffect {
+            write,
+            address,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        })
+    };
+    assert_eq!(records[1].memory, memory(true, 30, &[0, 0, 0], &[9841, -1, 0]));
+    assert_eq!(records[2].memory, memory(false, 30, &[9841, -1, 0], &[9841, -1, 0]));
+    assert_eq!(
+        records[2].writes,
+        vec![RegisterWrite {
+            register: 2,
+            old: 0,
+            new: -9842
+        }]
+    );
+    assert_eq!(records[3].memory, memory(true, 33, &[0], &[9841]));
+    assert_eq!(records[4].memory, memory(false, 33, &[9841], &[9841]));
+    assert!(records[4].writes.is_empty());
+    assert_eq!(records[5].memory, None);
+}
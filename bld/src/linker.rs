@@ -0,0 +1,404 @@
+// linker.rs - Combines relocatable objects into a btern executable.
+//
+// Linking runs in four steps:
+//   1. Collect the global symbols defined by every input object, then pull in
+//      archive members that define symbols still referenced but undefined,
+//      repeating until nothing more is needed.
+//   2. Merge sections of the same name, in input order, each contribution starting
+//      on a Word boundary. Output sections are laid out one after another from
+//      address 0: .text, .data, .bss, then any others in order of appearance.
+//   3. Give every symbol its final address.
+//   4. Patch every relocation with the address of the symbol it refers to.
+// Undefined and duplicate symbols and out-of-range relocations are all reported
+// together rather than stopping at the first.
+
+use btern_core::exe::{Executable, Permissions, Segment, Symbol};
+use btern_core::object::{Object, RelocationKind};
+use btern_core::{Trit, Tryte, TRYTES_PER_WORD};
+use std::collections::{HashMap, HashSet};
+
+/// Label that marks the program's entry point; without it execution starts at address 0.
+pub const ENTRY_SYMBOL: &str = "_start";
+
+/// Output sections that are always placed first, in this order.
+const SECTION_ORDER: [&str; 3] = [".text", ".data", ".bss"];
+
+/// An object to link, with the name used in messages and the map file.
+pub struct Input {
+    /// The object's file name, or `archive.a(member.o)` for an archive member.
+    pub name: String,
+    pub object: Object,
+}
+
+/// Where one input section ended up in the output.
+struct Contribution {
+    input: usize,
+    section: usize,
+    address: i64,
+}
+
+/// An output section, merged from input sections of the same name.
+struct OutputSection {
+    name: String,
+    address: i64,
+    size: usize,
+    permissions: Permissions,
+    contributions: Vec<Contribution>,
+}
+
+/// A symbol with its final address, for the map file.
+struct Placed {
+    name: String,
+    address: i64,
+    input: usize,
+    global: bool,
+}
+
+/// The result of a successful link.
+pub struct Linked {
+    pub exe: Executable,
+    /// Problems that did not stop the link.
+    pub warnings: Vec<String>,
+    inputs: Vec<Input>,
+    /// Archive members that were linked in, with the symbol that required each.
+    pulled: Vec<(usize, String)>,
+    sections: Vec<OutputSection>,
+    symbols: Vec<Placed>,
+}
+
+/// Rounds `address` up to the next Word boundary.
+fn align_word(address: i64) -> i64 {
+    let word = TRYTES_PER_WORD as i64;
+    (address + word - 1) / word * word
+}
+
+/// Returns the undefined symbols of `object` that relocations actually refer to.
+fn referenced_externs(object: &Object) -> HashSet<&str> {
+    object
+        .relocations
+        .iter()
+        .map(|reloc| &object.symbols[reloc.symbol])
+        .filter(|symbol| symbol.section.is_none())
+        .map(|symbol| symbol.name.as_str())
+        .collect()
+}
+
+/// Records the global symbols defined by `inputs[index]`, reporting any defined twice.
+fn define_globals(definitions: &mut HashMap<String, usize>, inputs: &[Input], index: usize, errors: &mut Vec<String>) {
+    for symbol in &inputs[index].object.symbols {
+        if !symbol.global || symbol.section.is_none() {
+            continue;
+        }
+        if let Some(&previous) = definitions.get(&symbol.name) {
+            errors.push(format!(
+                "duplicate symbol '{}' defined in {} and {}",
+                symbol.name, inputs[previous].name, inputs[index].name
+            ));
+        } else {
+            definitions.insert(symbol.name.clone(), index);
+        }
+    }
+}
+
+/// Links `objects`, plus whichever members of `archives` they need, into an executable.
+pub fn link(objects: Vec<Input>, archives: Vec<Vec<Input>>) -> Result<Linked, String> {
+    let mut inputs = objects;
+    let mut errors = Vec::new();
+
+    // --- 1. Global symbols and archive members ---
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    for index in 0..inputs.len() {
+        define_globals(&mut definitions, &inputs, index, &mut errors);
+    }
+
+    let mut members: Vec<Option<Input>> = archives.into_iter().flatten().map(Some).collect();
+    let mut pulled = Vec::new();
+    loop {
+        let needed: HashSet<String> = inputs
+            .iter()
+            .flat_map(|input| referenced_externs(&input.object))
+            .filter(|name| !definitions.contains_key(*name))
+            .map(str::to_string)
+            .collect();
+
+        let wanted = members.iter().position(|member| {
+            member.as_ref().is_some_and(|member| {
+                member
+                    .object
+                    .symbols
+                    .iter()
+                    .any(|s| s.global && s.section.is_some() && needed.contains(&s.name))
+            })
+        });
+        let Some(position) = wanted else { break };
+
+        let member = members[position].take().unwrap();
+        let reason = member
+            .object
+            .symbols
+            .iter()
+            .find(|s| s.global && s.section.is_some() && needed.contains(&s.name))
+            .map(|s| s.name.clone())
+            .unwrap_or_default();
+        inputs.push(member);
+        pulled.push((inputs.len() - 1, reason));
+        define_globals(&mut definitions, &inputs, inputs.len() - 1, &mut errors);
+    }
+
+    // --- 2. Section layout ---
+    let mut names: Vec<String> = SECTION_ORDER.iter().map(|name| name.to_string()).collect();
+    for input in &inputs {
+        for section in &input.object.sections {
+            if !names.contains(&section.name) {
+                names.push(section.name.clone());
+            }
+        }
+    }
+
+    let mut sections: Vec<OutputSection> = Vec::new();
+    // Final address of each input section, by input and section index.
+    let mut section_addresses: Vec<Vec<i64>> = inputs
+        .iter()
+        .map(|input| vec![0; input.object.sections.len()])
+        .collect();
+    let mut end = 0;
+    for name in names {
+        let start = align_word(end);
+        let mut output = OutputSection {
+            name,
+            address: start,
+            size: 0,
+            permissions: Permissions {
+                read: false,
+                write: false,
+                execute: false,
+            },
+            contributions: Vec::new(),
+        };
+        let mut cursor = start;
+        for (input_index, input) in inputs.iter().enumerate() {
+            for (section_index, section) in input.object.sections.iter().enumerate() {
+                if section.name != output.name {
+                    continue;
+                }
+                let address = align_word(cursor);
+                section_addresses[input_index][section_index] = address;
+                output.contributions.push(Contribution {
+                    input: input_index,
+                    section: section_index,
+                    address,
+                });
+                output.permissions.read |= section.permissions.read;
+                output.permissions.write |= section.permissions.write;
+                output.permissions.execute |= section.permissions.execute;
+                cursor = address + section.size as i64;
+            }
+        }
+        output.size = (cursor - start) as usize;
+        if output.size > 0 {
+            end = cursor;
+            sections.push(output);
+        }
+    }
+
+    // --- 3. Symbol addresses ---
+    let address_of = |input: usize, symbol: usize| -> Option<i64> {
+        let symbol = &inputs[input].object.symbols[symbol];
+        symbol
+            .section
+            .map(|section| section_addresses[input][section] + symbol.offset)
+    };
+    let mut symbols: Vec<Placed> = Vec::new();
+    for (input_index, input) in inputs.iter().enumerate() {
+        for (symbol_index, symbol) in input.object.symbols.iter().enumerate() {
+            if let Some(address) = address_of(input_index, symbol_index) {
+                symbols.push(Placed {
+                    name: symbol.name.clone(),
+                    address,
+                    input: input_index,
+                    global: symbol.global,
+                });
+            }
+        }
+    }
+    symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+    let global_address = |name: &str| -> Option<i64> {
+        symbols
+            .iter()
+            .find(|placed| placed.global && placed.name == name)
+            .map(|placed| placed.address)
+    };
+
+    // --- 4. Contents and relocations ---
+    let mut contents: Vec<Vec<Tryte>> = Vec::new();
+    for output in &sections {
+        let mut data = Vec::new();
+        for contribution in &output.contributions {
+            let section = &inputs[contribution.input].object.sections[contribution.section];
+            if section.data.is_empty() {
+                continue;
+            }
+            let start = (contribution.address - output.address) as usize;
+            data.resize(start, [Trit::Z; 9]);
+            data.extend_from_slice(&section.data);
+        }
+        contents.push(data);
+    }
+
+    let mut reported: HashSet<(String, usize)> = HashSet::new();
+    for (output_index, output) in sections.iter().enumerate() {
+        for contribution in &output.contributions {
+            let input = &inputs[contribution.input];
+            for reloc in input
+                .object
+                .relocations
+                .iter()
+                .filter(|r| r.section == contribution.section)
+            {
+                let symbol = &input.object.symbols[reloc.symbol];
+                let target = match address_of(contribution.input, reloc.symbol) {
+                    Some(address) => address,
+                    None => match global_address(&symbol.name) {
+                        Some(address) => address,
+                        None => {
+                            if reported.insert((symbol.name.clone(), contribution.input)) {
+                                errors.push(format!("undefined reference to '{}' in {}", symbol.name, input.name));
+                            }
+                            continue;
+                        }
+                    },
+                };
+
+                let site = contribution.address + reloc.offset as i64;
+                let value = match reloc.kind {
+                    RelocationKind::Rel12 => target + reloc.addend - site,
+                    _ => target + reloc.addend,
+                };
+                let start = (site - output.address) as usize;
+                let patched = &mut contents[output_index][start..start + reloc.kind.width()];
+                if !reloc.kind.patch(patched, value) {
+                    errors.push(format!(
+                        "relocation {} against '{}' in {} at {}+{} (address {}): value {} does not fit ({}..{})",
+                        reloc.kind.name(),
+                        symbol.name,
+                        input.name,
+                        output.name,
+                        site - output.address,
+                        site,
+                        value,
+                        -reloc.kind.max_value(),
+                        reloc.kind.max_value()
+                    ));
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    let mut warnings = Vec::new();
+    let entry = match global_address(ENTRY_SYMBOL) {
+        Some(address) => address,
+        None => {
+            if let Some(local) = symbols.iter().find(|placed| placed.name == ENTRY_SYMBOL) {
+                warnings.push(format!(
+                    "{} in {} is not .global; the entry point defaults to 0",
+                    ENTRY_SYMBOL, inputs[local.input].name
+                ));
+            }
+            0
+        }
+    };
+
+    // Keep every global, and each local whose name is unique, so labels stay unambiguous.
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for placed in &symbols {
+        *name_counts.entry(placed.name.as_str()).or_default() += 1;
+    }
+    let exe_symbols = symbols
+        .iter()
+        .filter(|placed| placed.global || name_counts[placed.name.as_str()] == 1)
+        .map(|placed| Symbol {
+            name: placed.name.clone(),
+            address: placed.address,
+        })
+        .collect();
+
+    let segments = sections
+        .iter()
+        .zip(contents)
+        .map(|(output, data)| Segment {
+            name: output.name.clone(),
+            address: output.address,
+            size: output.size,
+            permissions: output.permissions,
+            data,
+        })
+        .collect();
+
+    Ok(Linked {
+        exe: Executable {
+            entry,
+            segments,
+            symbols: exe_symbols,
+        },
+        warnings,
+        inputs,
+        pulled,
+        sections,
+        symbols,
+    })
+}
+
+impl Linked {
+    /// Renders the link map: the entry point, where each input section was placed,
+    /// which archive members were linked in and why, and every symbol's address.
+    pub fn map(&self) -> String {
+        let mut map = String::new();
+        map.push_str("; bld link map\n\n");
+
+        let entry_name = self
+            .exe
+            .symbols
+            .iter()
+            .find(|symbol| symbol.address == self.exe.entry && symbol.name == ENTRY_SYMBOL)
+            .map_or("", |_| " (_start)");
+        map.push_str(&format!("Entry point: {:05}{}\n\n", self.exe.entry, entry_name));
+
+        map.push_str("Sections:\n");
+        for output in &self.sections {
+            map.push_str(&format!(
+                "  {:<12} {:05}  size {:5}  {}\n",
+                output.name, output.address, output.size, output.permissions
+            ));
+            for contribution in &output.contributions {
+                let section = &self.inputs[contribution.input].object.sections[contribution.section];
+                map.push_str(&format!(
+                    "      {:05}  size {:5}  {}\n",
+                    contribution.address, section.size, self.inputs[contribution.input].name
+                ));
+            }
+        }
+
+        if !self.pulled.is_empty() {
+            map.push_str("\nArchive members linked:\n");
+            for (input, reason) in &self.pulled {
+                map.push_str(&format!("  {:<32} for '{}'\n", self.inputs[*input].name, reason));
+            }
+        }
+
+        map.push_str("\nSymbols:\n");
+        for placed in &self.symbols {
+            map.push_str(&format!(
+                "  {:05}  {:<24} {:<6} {}\n",
+                placed.address,
+                placed.name,
+                if placed.global { "global" } else { "local" },
+                self.inputs[placed.input].name
+            ));
+        }
+        map
+    }
+}
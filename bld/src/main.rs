@@ -0,0 +1,170 @@
+// main.rs - The entry point for the btern linker (bld).
+//
+// bld links relocatable objects written by `basm -c`, and the members of static
+// archives that they need, into a single executable. It can also bundle objects
+// into an archive with `--archive`.
+
+use btern_core::exe::write_executable;
+use btern_core::object::{read_archive, read_object, write_archive, ArchiveMember, ARCHIVE_MAGIC, OBJECT_MAGIC};
+use btern_core::TRYTES_PER_WORD;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod linker;
+
+use linker::Input;
+
+const USAGE: &str = "\
+Usage: bld <input.o|input.a>... [-o <output.bin>] [--map <output.map>] [--strip]
+       bld --archive <output.a> <input.o>...
+
+Options:
+  -o <FILE>         Output executable (default: the first input with a .bin extension)
+  --map <FILE>      Write a link map: section placement, archive members and symbols
+  --strip           Leave the symbol table out of the executable
+  --archive <FILE>  Bundle the input objects into a static archive instead of linking";
+
+/// Command-line options accepted by bld.
+struct Options {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    map: Option<PathBuf>,
+    strip: bool,
+    archive: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        inputs: Vec::new(),
+        output: None,
+        map: None,
+        strip: false,
+        archive: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut path = || {
+            iter.next()
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} requires a path\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(path()?),
+            "--map" => options.map = Some(path()?),
+            "--archive" => options.archive = Some(path()?),
+            "--strip" => options.strip = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'\n{}", arg, USAGE)),
+            _ => options.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if options.inputs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    if options.archive.is_some() && (options.output.is_some() || options.map.is_some() || options.strip) {
+        return Err(format!(
+            "--archive cannot be combined with -o, --map or --strip\n{}",
+            USAGE
+        ));
+    }
+    Ok(options)
+}
+
+/// Returns the file name of `path` for use in messages and archive member names.
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// Bundles the objects named in `inputs` into the archive at `output`.
+fn create_archive(inputs: &[PathBuf], output: &Path) -> Result<(), String> {
+    let mut members = Vec::new();
+    for path in inputs {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let object = read_object(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        members.push(ArchiveMember {
+            name: file_name(path),
+            object,
+        });
+    }
+    fs::write(output, write_archive(&members)).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    println!("Archived {} object(s) to {}", members.len(), output.display());
+    Ok(())
+}
+
+/// Links the objects and archives named in `options` into an executable.
+fn link(options: &Options) -> Result<(), String> {
+    let mut objects = Vec::new();
+    let mut archives = Vec::new();
+    for path in &options.inputs {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let name = file_name(path);
+        if bytes.starts_with(&OBJECT_MAGIC) {
+            let object = read_object(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            objects.push(Input { name, object });
+        } else if bytes.starts_with(&ARCHIVE_MAGIC) {
+            let members = read_archive(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            archives.push(
+                members
+                    .into_iter()
+                    .map(|member| Input {
+                        name: format!("{}({})", name, member.name),
+                        object: member.object,
+                    })
+                    .collect(),
+            );
+        } else {
+            return Err(format!("{}: not a btern object file or archive", path.display()));
+        }
+    }
+
+    let mut linked = linker::link(objects, archives)?;
+    for warning in &linked.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if let Some(map_path) = &options.map {
+        fs::write(map_path, linked.map()).map_err(|e| format!("Failed to write {}: {}", map_path.display(), e))?;
+    }
+    if options.strip {
+        linked.exe.symbols.clear();
+    }
+
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| options.inputs[0].with_extension("bin"));
+    let data = write_executable(&linked.exe);
+    fs::write(&output, &data).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    let trytes = linked.exe.total_trytes();
+    println!(
+        "Linked {} Trytes ({} Words) in {} segment(s) -> {} bytes, entry point {}",
+        trytes,
+        trytes.div_ceil(TRYTES_PER_WORD),
+        linked.exe.segments.len(),
+        data.len(),
+        linked.exe.entry
+    );
+    println!("Successfully linked program to {}", output.display());
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|options| match &options.archive {
+        Some(archive) => create_archive(&options.inputs, archive),
+        None => link(&options),
+    });
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,200 @@
+// dense.rs - Dense trit packing: 5 trits per byte.
+//
+// 3^5 = 243 fits in a byte, so five trits can share one byte at about 95%
+// storage efficiency (versus 4 trits per byte for BCT). Each byte holds
+// sum((t_k + 1) * 3^k) for k = 0..4, the first trit in the lowest digit;
+// byte values 243-255 are invalid.
+//
+// A dense stream is a sequence of frames, each a u16 little-endian trit count
+// followed by ceil(count / 5) packed bytes. A frame with a count of 0 ends the
+// stream. Framing lets both sides work incrementally without knowing the total
+// length up front, and lets a reader tell padding trits from data.
+
+use std::io::{self, Read, Write};
+
+use crate::{Trit, Tryte, Word};
+
+/// Number of trits packed into each byte.
+pub const TRITS_PER_BYTE: usize = 5;
+
+/// Largest number of trits in one frame (a multiple of 5 that fits in a u16).
+pub const MAX_FRAME_TRITS: usize = 65535;
+
+/// Packs up to five trits into one byte. Missing trits are padded with Z.
+fn pack_byte(trits: &[Trit]) -> u8 {
+    let mut value = 0u8;
+    for slot in (0..TRITS_PER_BYTE).rev() {
+        let trit = trits.get(slot).copied().unwrap_or(Trit::Z);
+        value = value * 3 + (trit.to_i8() + 1) as u8;
+    }
+    value
+}
+
+/// Unpacks one byte into five trits, rejecting values above 242.
+fn unpack_byte(byte: u8) -> io::Result<[Trit; TRITS_PER_BYTE]> {
+    if byte >= 243 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid dense byte {} (must be below 243)", byte),
+        ));
+    }
+
+    let mut trits = [Trit::Z; TRITS_PER_BYTE];
+    let mut value = byte;
+    for trit in trits.iter_mut() {
+        *trit = Trit::from_i8((value % 3) as i8 - 1).unwrap();
+        value /= 3;
+    }
+    Ok(trits)
+}
+
+/// Streaming encoder that writes trits to `W` in the dense framed format.
+/// Call `finish` to flush the last frame and write the end-of-stream marker.
+pub struct DenseEncoder<W: Write> {
+    writer: W,
+    pending: Vec<Trit>,
+}
+
+impl<W: Write> DenseEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: Vec::with_capacity(MAX_FRAME_TRITS),
+        }
+    }
+
+    /// Appends trits (ordered LSB first, as everywhere else) to the stream.
+    pub fn write_trits(&mut self, trits: &[Trit]) -> io::Result<()> {
+        for &trit in trits {
+            self.pending.push(trit);
+            if self.pending.len() == MAX_FRAME_TRITS {
+                self.flush_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the 9 trits of a Tryte.
+    pub fn write_tryte(&mut self, tryte: &Tryte) -> io::Result<()> {
+        self.write_trits(tryte)
+    }
+
+    /// Appends the 27 trits of a Word.
+    pub fn write_word(&mut self, word: &Word) -> io::Result<()> {
+        self.write_trits(word)
+    }
+
+    /// Writes any buffered trits as a frame.
+    fn flush_frame(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let packed: Vec<u8> = self.pending.chunks(TRITS_PER_BYTE).map(pack_byte).collect();
+        self.writer.write_all(&(self.pending.len() as u16).to_le_bytes())?;
+        self.writer.write_all(&packed)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Flushes the final frame, writes the end-of-stream marker and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_frame()?;
+        self.writer.write_all(&0u16.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Streaming decoder that reads trits from a dense framed stream.
+/// Iterating yields each trit in order and stops at the end-of-stream marker.
+pub struct DenseDecoder<R: Read> {
+    reader: R,
+    /// Trits left in the current frame.
+    frame_remaining: usize,
+    /// Unpacked trits of the current byte that have not been returned yet.
+    byte_trits: [Trit; TRITS_PER_BYTE],
+    byte_pos: usize,
+    finished: bool,
+}
+
+impl<R: Read> DenseDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            frame_remaining: 0,
+            byte_trits: [Trit::Z; TRITS_PER_BYTE],
+            byte_pos: TRITS_PER_BYTE,
+            finished: false,
+        }
+    }
+
+    /// Reads the next trit, or None at the end of the stream.
+    pub fn read_trit(&mut self) -> io::Result<Option<Trit>> {
+        if self.frame_remaining == 0 {
+            if self.finished {
+                return Ok(None);
+            }
+            let mut header = [0u8; 2];
+            self.reader.read_exact(&mut header)?;
+            self.frame_remaining = u16::from_le_bytes(header) as usize;
+            // A new frame always starts on a byte boundary.
+            self.byte_pos = TRITS_PER_BYTE;
+            if self.frame_remaining == 0 {
+                self.finished = true;
+                return Ok(None);
+            }
+        }
+
+        if self.byte_pos == TRITS_PER_BYTE {
+            let mut byte = [0u8; 1];
+            self.reader.read_exact(&mut byte)?;
+            self.byte_trits = unpack_byte(byte[0])?;
+            self.byte_pos = 0;
+        }
+
+        let trit = self.byte_trits[self.byte_pos];
+        self.byte_pos += 1;
+        self.frame_remaining -= 1;
+        Ok(Some(trit))
+    }
+
+    /// Reads the next 9 trits as a Tryte. Returns None at the end of the stream,
+    /// and an error if the stream ends part-way through a Tryte.
+    pub fn read_tryte(&mut self) -> io::Result<Option<Tryte>> {
+        let mut tryte = [Trit::Z; 9];
+        for (i, slot) in tryte.iter_mut().enumerate() {
+            match self.read_trit()? {
+                Some(trit) => *slot = trit,
+                None if i == 0 => return Ok(None),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "dense stream ends in the middle of a tryte",
+                    ))
+                }
+            }
+        }
+        Ok(Some(tryte))
+    }
+}
+
+impl<R: Read> Iterator for DenseDecoder<R> {
+    type Item = io::Result<Trit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_trit().transpose()
+    }
+}
+
+/// Encodes a trit slice as a complete dense stream.
+pub fn encode_trits(trits: &[Trit]) -> Vec<u8> {
+    let mut encoder = DenseEncoder::new(Vec::new());
+    // Writing to a Vec cannot fail.
+    encoder.write_trits(trits).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decodes a complete dense stream into trits.
+pub fn decode_trits(bytes: &[u8]) -> io::Result<Vec<Trit>> {
+    DenseDecoder::new(bytes).collect()
+}
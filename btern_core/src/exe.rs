@@ -0,0 +1,415 @@
+// exe.rs - The btern executable format: named segments, entry point and symbols.
+//
+// Unlike a flat image, which is always loaded at tryte address 0, an executable
+// places each segment at its own load address and records where execution starts.
+//
+//   Offset  Size  Field
+//   0       4     Magic "BTEX"
+//   4       1     Format version (1)
+//   5       8     Entry point: tryte address, i64 little-endian
+//   13      2     Segment count, u16 little-endian
+//   15      4     Symbol count, u32 little-endian
+//   19      ...   Segments, each:
+//                   name length (u8) and name (UTF-8)
+//                   load address: tryte address, i64 little-endian
+//                   size in Trytes, u64 little-endian
+//                   permissions, u8 (1 = read, 2 = write, 4 = execute)
+//                   initialized length in Trytes, u64 little-endian (0 for bss)
+//                   initialized contents, BCT-packed as in image.rs
+//   ...     ...   Symbols, each: name length (u8), name, address (i64 little-endian)
+//   end-4   4     Checksum: 32-bit FNV-1a of every preceding byte, little-endian
+//
+// Trytes of a segment beyond its initialized length are zero-filled on load.
+
+use std::fmt;
+
+use crate::image::{pack_bct, read_image, unpack_bct, ImageError, ProgramImage, FORMAT_VERSION};
+use crate::object::{ARCHIVE_MAGIC, OBJECT_MAGIC};
+use crate::{Trit, Tryte};
+
+/// Magic number at the start of an executable.
+pub const EXE_MAGIC: [u8; 4] = *b"BTEX";
+
+/// Size of the fixed executable header in bytes.
+const EXE_HEADER_BYTES: usize = 19;
+
+/// Access permissions of a segment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Read and execute: program code.
+    pub const RX: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    /// Read and write: initialized and zero-initialized data.
+    pub const RW: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    /// Everything: used for flat images, which do not separate code from data.
+    pub const RWX: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+
+    pub(crate) fn to_bits(self) -> u8 {
+        (self.read as u8) | (self.write as u8) << 1 | (self.execute as u8) << 2
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        Permissions {
+            read: bits & 1 != 0,
+            write: bits & 2 != 0,
+            execute: bits & 4 != 0,
+        }
+    }
+}
+
+/// Displays permissions in the familiar `rwx` form, e.g. `r-x`.
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.execute { 'x' } else { '-' }
+        )
+    }
+}
+
+/// A contiguous block of memory to be loaded at a fixed address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Segment name, e.g. ".text", ".data" or ".bss".
+    pub name: String,
+    /// Tryte address of the first Tryte.
+    pub address: i64,
+    /// Size in memory, in Trytes. At least `data.len()`.
+    pub size: usize,
+    pub permissions: Permissions,
+    /// Initialized contents; the remaining `size - data.len()` Trytes are zero.
+    pub data: Vec<Tryte>,
+}
+
+/// A named address recorded in the executable, e.g. a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: i64,
+}
+
+/// A loadable btern program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    /// Tryte address where execution starts.
+    pub entry: i64,
+    pub segments: Vec<Segment>,
+    /// Optional symbol table; empty if stripped.
+    pub symbols: Vec<Symbol>,
+}
+
+impl Executable {
+    /// Looks up a symbol's address by name.
+    pub fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.address)
+    }
+
+    /// Returns the total size of all segments in Trytes.
+    pub fn total_trytes(&self) -> usize {
+        self.segments.iter().map(|s| s.size).sum()
+    }
+
+    /// Lays the segments out in a flat image starting at tryte address 0.
+    /// Zero-filled space after the last initialized Tryte is left out, since
+    /// memory starts out zeroed anyway. Fails if a segment ends past the
+    /// addressable range.
+    pub fn flatten(&self) -> Result<ProgramImage, ImageError> {
+        let mut end = 0;
+        for segment in self.segments.iter().filter(|s| !s.data.is_empty()) {
+            let segment_end = usize::try_from(segment.address)
+                .ok()
+                .and_then(|start| start.checked_add(segment.data.len()))
+                .ok_or_else(|| {
+                    ImageError::InvalidSegment(format!(
+                        "{} at address {} cannot be placed in a flat image",
+                        segment.name, segment.address
+                    ))
+                })?;
+            end = end.max(segment_end);
+        }
+
+        let mut trytes = vec![[Trit::Z; 9]; end];
+        for segment in self.segments.iter().filter(|s| !s.data.is_empty()) {
+            let start = segment.address as usize;
+            trytes[start..start + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        Ok(ProgramImage {
+            entry: self.entry,
+            trytes,
+        })
+    }
+}
+
+/// A flat image becomes a single read/write/execute segment at address 0.
+impl From<ProgramImage> for Executable {
+    fn from(image: ProgramImage) -> Self {
+        Executable {
+            entry: image.entry,
+            segments: vec![Segment {
+                name: "image".to_string(),
+                address: 0,
+                size: image.trytes.len(),
+                permissions: Permissions::RWX,
+                data: image.trytes,
+            }],
+            symbols: Vec::new(),
+        }
+    }
+}
+
+/// Computes the 32-bit FNV-1a hash used as the executable checksum.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for &byte in bytes {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Appends a length-prefixed name. Names longer than 255 bytes are cut short.
+pub(crate) fn push_name(bytes: &mut Vec<u8>, name: &str) {
+    let mut len = name.len().min(u8::MAX as usize);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    bytes.push(len as u8);
+    bytes.extend_from_slice(&name.as_bytes()[..len]);
+}
+
+/// Encodes an executable.
+pub fn write_executable(exe: &Executable) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&EXE_MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&exe.entry.to_le_bytes());
+    bytes.extend_from_slice(&(exe.segments.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(exe.symbols.len() as u32).to_le_bytes());
+
+    for segment in &exe.segments {
+        push_name(&mut bytes, &segment.name);
+        bytes.extend_from_slice(&segment.address.to_le_bytes());
+        bytes.extend_from_slice(&(segment.size as u64).to_le_bytes());
+        bytes.push(segment.permissions.to_bits());
+        bytes.extend_from_slice(&(segment.data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&pack_bct(&segment.data));
+    }
+
+    for symbol in &exe.symbols {
+        push_name(&mut bytes, &symbol.name);
+        bytes.extend_from_slice(&symbol.address.to_le_bytes());
+    }
+
+    let checksum = fnv1a(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Sequential reader over a container's bytes that reports truncation.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, count: usize) -> Result<&'a [u8], ImageError> {
+        if self.bytes.len() - self.pos < count {
+            return Err(ImageError::Truncated {
+                expected: self.pos.saturating_add(count),
+                found: self.bytes.len(),
+            });
+        }
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ImageError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ImageError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ImageError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn i64(&mut self) -> Result<i64, ImageError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn name(&mut self) -> Result<String, ImageError> {
+        let len = self.u8()? as usize;
+        let offset = self.pos;
+        let raw = self.take(len)?;
+        String::from_utf8(raw.to_vec()).map_err(|_| ImageError::InvalidName { offset })
+    }
+}
+
+/// Decodes an executable written by write_executable, verifying its checksum.
+pub fn read_executable(bytes: &[u8]) -> Result<Executable, ImageError> {
+    if bytes.len() < EXE_HEADER_BYTES + 4 {
+        return Err(ImageError::Truncated {
+            expected: EXE_HEADER_BYTES + 4,
+            found: bytes.len(),
+        });
+    }
+
+    let (body, trailer) = bytes.split_at(bytes.len() - 4);
+    let stored = u32::from_le_bytes(trailer.try_into().unwrap());
+    let computed = fnv1a(body);
+    if stored != computed {
+        return Err(ImageError::ChecksumMismatch { stored, computed });
+    }
+
+    let mut reader = Reader { bytes: body, pos: 4 };
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let entry = reader.i64()?;
+    let segment_count = reader.u16()? as usize;
+    let symbol_count = reader.u32()? as usize;
+
+    let mut segments: Vec<Segment> = Vec::with_capacity(segment_count);
+    for _ in 0..segment_count {
+        let name = reader.name()?;
+        let address = reader.i64()?;
+        let size = reader.u64()?;
+        let permissions = Permissions::from_bits(reader.u8()?);
+        let data_len = reader.u64()? as usize;
+
+        if address < 0 {
+            return Err(ImageError::InvalidSegment(format!(
+                "{} has negative address {}",
+                name, address
+            )));
+        }
+        let end = i64::try_from(size).ok().and_then(|size| address.checked_add(size));
+        let Some(end) = end else {
+            return Err(ImageError::InvalidSegment(format!(
+                "{} has {} Trytes at address {}, past the end of the address space",
+                name, size, address
+            )));
+        };
+        let size = size as usize;
+        if data_len > size {
+            return Err(ImageError::InvalidSegment(format!(
+                "{} has {} initialized Trytes but a size of {}",
+                name, data_len, size
+            )));
+        }
+        if let Some(other) = segments
+            .iter()
+            .find(|s| address < s.address + s.size as i64 && s.address < end)
+        {
+            return Err(ImageError::InvalidSegment(format!("{} overlaps {}", name, other.name)));
+        }
+
+        let packed_len = data_len.saturating_mul(9).div_ceil(4);
+        let offset = reader.pos;
+        let data = unpack_bct(reader.take(packed_len)?, data_len, offset)?;
+        segments.push(Segment {
+            name,
+            address,
+            size,
+            permissions,
+            data,
+        });
+    }
+
+    let mut symbols = Vec::with_capacity(symbol_count.min(body.len()));
+    for _ in 0..symbol_count {
+        let name = reader.name()?;
+        let address = reader.i64()?;
+        symbols.push(Symbol { name, address });
+    }
+
+    Ok(Executable {
+        entry,
+        segments,
+        symbols,
+    })
+}
+
+/// Decodes any program file: an executable, or a flat image (packed, dense or
+/// legacy raw), which becomes a single segment at address 0. Relocatable objects
+/// and archives are rejected, as they must be linked first.
+pub fn read_program(bytes: &[u8]) -> Result<Executable, ImageError> {
+    if bytes.starts_with(&EXE_MAGIC) {
+        read_executable(bytes)
+    } else if bytes.starts_with(&OBJECT_MAGIC) || bytes.starts_with(&ARCHIVE_MAGIC) {
+        Err(ImageError::NotLinked)
+    } else {
+        read_image(bytes).map(Executable::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i64_to_tryte;
+
+    fn segment(name: &str, address: i64, size: usize, data: &[i64]) -> Segment {
+        Segment {
+            name: name.to_string(),
+            address,
+            size,
+            permissions: Permissions::RW,
+            data: data.iter().map(|&v| i64_to_tryte(v)).collect(),
+        }
+    }
+
+    #[test]
+    fn flatten_ignores_a_trailing_bss() {
+        let exe = Executable {
+            entry: 0,
+            segments: vec![
+                segment(".text", 0, 3, &[1, 2, 3]),
+                segment(".data", 3, 4, &[4, 5, 6, 7]),
+                segment(".bss", 9, 5, &[]),
+            ],
+            symbols: Vec::new(),
+        };
+        let image = exe.flatten().unwrap();
+        let expected: Vec<Tryte> = (1..=7).map(i64_to_tryte).collect();
+        assert_eq!(image.trytes, expected);
+    }
+
+    #[test]
+    fn segments_past_the_address_space_are_rejected() {
+        let exe = Executable {
+            entry: 0,
+            segments: vec![segment(".bss", 1, usize::MAX, &[])],
+            symbols: Vec::new(),
+        };
+        let bytes = write_executable(&exe);
+        assert!(matches!(read_executable(&bytes), Err(ImageError::InvalidSegment(_))));
+    }
+}
@@ -0,0 +1,281 @@
+// image.rs - Program image formats shared by basm, bemu and bdis.
+//
+// A program image is a sequence of Trytes to be placed in memory starting at
+// tryte address 0, plus the address execution starts at. Three encodings exist;
+// basm writes the executable format of exe.rs by default and these with --format:
+//
+// Packed BCT (`--format bct`):
+//
+//   Offset  Size  Field
+//   0       4     Magic "BTRN"
+//   4       1     Format version (1)
+//   5       8     Entry point: tryte address, i64 little-endian
+//   13      8     Length in Trytes, u64 little-endian
+//   21      ...   Payload: trits packed 4 per byte using Trit::to_bct, the first
+//                 trit in the lowest two bits. Unused trits in the final byte
+//                 are padded with Z (01), so the reserved 11 pattern never appears.
+//
+// Dense (`--format dense`, for large weight tables and memory snapshots):
+//
+//   Offset  Size  Field
+//   0       4     Magic "BTD5"
+//   4       1     Format version (1)
+//   5       8     Entry point: tryte address, i64 little-endian
+//   13      ...   Memory contents as a framed dense stream (see dense.rs),
+//                 5 trits per byte; the trit count must be a multiple of 9.
+//
+// Legacy raw (`--format raw`): one byte per trit holding its i8 value (-1, 0
+// or 1), no header, entry point 0. read_image tells the formats apart by their
+// magic numbers.
+
+use std::fmt;
+
+use crate::dense::{DenseDecoder, DenseEncoder};
+use crate::{Trit, Tryte};
+
+/// Magic number at the start of a packed image.
+pub const MAGIC: [u8; 4] = *b"BTRN";
+
+/// Magic number at the start of a dense image.
+pub const DENSE_MAGIC: [u8; 4] = *b"BTD5";
+
+/// Size of the dense image header in bytes.
+const DENSE_HEADER_BYTES: usize = 13;
+
+/// Current version of the packed image format.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of the packed image header in bytes.
+const HEADER_BYTES: usize = 21;
+
+/// A program ready to be placed in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramImage {
+    /// Tryte address where execution starts.
+    pub entry: i64,
+    /// Memory contents, starting at tryte address 0.
+    pub trytes: Vec<Tryte>,
+}
+
+/// Errors that can occur while reading a program image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    /// A byte in a raw image is not the i8 value of a Trit (-1, 0 or 1).
+    InvalidTrit { offset: usize, value: u8 },
+    /// The image does not contain a whole number of Trytes.
+    PartialTryte { trits: usize },
+    /// The packed image was written by an unsupported format version.
+    UnsupportedVersion(u8),
+    /// The packed image ends before its header or payload is complete.
+    Truncated { expected: usize, found: usize },
+    /// A packed trit uses the reserved BCT pattern 11, which indicates corruption.
+    ReservedBct { offset: usize },
+    /// The dense payload could not be decoded.
+    Dense(String),
+    /// The executable's checksum does not match its contents.
+    ChecksumMismatch { stored: u32, computed: u32 },
+    /// An executable segment is malformed (bad size or placement).
+    InvalidSegment(String),
+    /// A name stored in an executable, object or archive is not valid UTF-8.
+    InvalidName { offset: usize },
+    /// A relocatable object is malformed (bad section, symbol or relocation reference).
+    InvalidObject(String),
+    /// The file is a relocatable object or archive, which cannot be loaded directly.
+    NotLinked,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::InvalidTrit { offset, value } => write!(
+                f,
+                "Invalid trit value in program binary at byte {}: {}",
+                offset, *value as i8
+            ),
+            ImageError::PartialTryte { trits } => write!(
+                f,
+                "Program size is not a multiple of 9 trits (1 Tryte). Size: {} trits",
+                trits
+            ),
+            ImageError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported program image version: {}", version)
+            }
+            ImageError::Truncated { expected, found } => write!(
+                f,
+                "Program image is truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            ImageError::ReservedBct { offset } => write!(
+                f,
+                "Corrupt program image: reserved BCT pattern 11 in byte {}",
+                offset
+            ),
+            ImageError::Dense(message) => write!(f, "Corrupt dense program image: {}", message),
+            ImageError::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "Corrupt executable: checksum {:08x} does not match contents ({:08x})",
+                stored, computed
+            ),
+            ImageError::InvalidSegment(message) => write!(f, "Invalid executable segment: {}", message),
+            ImageError::InvalidName { offset } => write!(f, "Corrupt file: name at byte {} is not valid UTF-8", offset),
+            ImageError::InvalidObject(message) => write!(f, "Invalid object file: {}", message),
+            ImageError::NotLinked => write!(f, "This is a relocatable object or archive; link it with bld first"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Encodes Trytes in the raw format: one byte per trit, holding its i8 value (-1, 0 or 1).
+pub fn write_raw_image(trytes: &[Tryte]) -> Vec<u8> {
+    trytes
+        .iter()
+        .flat_map(|tryte| tryte.iter().map(|trit| trit.to_i8() as u8))
+        .collect()
+}
+
+/// Decodes a raw image written by write_raw_image.
+pub fn read_raw_image(bytes: &[u8]) -> Result<Vec<Tryte>, ImageError> {
+    if !bytes.len().is_multiple_of(9) {
+        return Err(ImageError::PartialTryte { trits: bytes.len() });
+    }
+
+    let mut trytes = Vec::with_capacity(bytes.len() / 9);
+    for (chunk_idx, chunk) in bytes.chunks(9).enumerate() {
+        let mut tryte = [Trit::Z; 9];
+        for (i, &byte) in chunk.iter().enumerate() {
+            tryte[i] = Trit::from_i8(byte as i8).map_err(|_| ImageError::InvalidTrit {
+                offset: chunk_idx * 9 + i,
+                value: byte,
+            })?;
+        }
+        trytes.push(tryte);
+    }
+    Ok(trytes)
+}
+
+/// Encodes a program in the packed BCT format (4 trits per byte).
+pub fn write_image(image: &ProgramImage) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_BYTES + (image.trytes.len() * 9).div_ceil(4));
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&image.entry.to_le_bytes());
+    bytes.extend_from_slice(&(image.trytes.len() as u64).to_le_bytes());
+
+    bytes.extend_from_slice(&pack_bct(&image.trytes));
+    bytes
+}
+
+/// Packs Trytes 4 trits per byte using Trit::to_bct, the first trit in the lowest bits.
+pub(crate) fn pack_bct(trytes: &[Tryte]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((trytes.len() * 9).div_ceil(4));
+    for group in trytes.concat().chunks(4) {
+        // Pad a short final group with Z so the byte never contains the reserved 11 pattern.
+        let mut byte = 0u8;
+        for slot in 0..4 {
+            let trit = group.get(slot).copied().unwrap_or(Trit::Z);
+            byte |= trit.to_bct() << (2 * slot);
+        }
+        bytes.push(byte);
+    }
+    bytes
+}
+
+/// Unpacks `tryte_count` Trytes from BCT bytes produced by pack_bct.
+/// `base_offset` is the position of `bytes` within the file, for error reporting.
+pub(crate) fn unpack_bct(bytes: &[u8], tryte_count: usize, base_offset: usize) -> Result<Vec<Tryte>, ImageError> {
+    let trit_count = tryte_count.saturating_mul(9);
+    let needed = trit_count.div_ceil(4);
+    if bytes.len() < needed {
+        return Err(ImageError::Truncated {
+            expected: base_offset + needed,
+            found: base_offset + bytes.len(),
+        });
+    }
+
+    let mut trits = Vec::with_capacity(trit_count);
+    for (i, &byte) in bytes[..needed].iter().enumerate() {
+        for slot in 0..4 {
+            let trit = Trit::from_bct(byte >> (2 * slot)).map_err(|_| ImageError::ReservedBct {
+                offset: base_offset + i,
+            })?;
+            trits.push(trit);
+        }
+    }
+    trits.truncate(trit_count);
+
+    Ok(trits
+        .chunks_exact(9)
+        .map(|chunk| {
+            let mut tryte = [Trit::Z; 9];
+            tryte.copy_from_slice(chunk);
+            tryte
+        })
+        .collect())
+}
+
+/// Encodes a program in the dense format (5 trits per byte).
+pub fn write_dense_image(image: &ProgramImage) -> Vec<u8> {
+    let mut header = Vec::with_capacity(DENSE_HEADER_BYTES);
+    header.extend_from_slice(&DENSE_MAGIC);
+    header.push(FORMAT_VERSION);
+    header.extend_from_slice(&image.entry.to_le_bytes());
+
+    let mut encoder = DenseEncoder::new(header);
+    // Writing to a Vec cannot fail.
+    for tryte in &image.trytes {
+        encoder.write_tryte(tryte).unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+/// Decodes the body of a dense image (everything after the magic number).
+fn read_dense_image(bytes: &[u8]) -> Result<ProgramImage, ImageError> {
+    if bytes.len() < DENSE_HEADER_BYTES {
+        return Err(ImageError::Truncated {
+            expected: DENSE_HEADER_BYTES,
+            found: bytes.len(),
+        });
+    }
+    let version = bytes[4];
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let entry = i64::from_le_bytes(bytes[5..13].try_into().unwrap());
+
+    let mut decoder = DenseDecoder::new(&bytes[DENSE_HEADER_BYTES..]);
+    let mut trytes = Vec::new();
+    while let Some(tryte) = decoder.read_tryte().map_err(|e| ImageError::Dense(e.to_string()))? {
+        trytes.push(tryte);
+    }
+    Ok(ProgramImage { entry, trytes })
+}
+
+/// Decodes a program image, detecting whether it is packed, dense or legacy raw.
+pub fn read_image(bytes: &[u8]) -> Result<ProgramImage, ImageError> {
+    if bytes.starts_with(&DENSE_MAGIC) {
+        return read_dense_image(bytes);
+    }
+    if !bytes.starts_with(&MAGIC) {
+        return Ok(ProgramImage {
+            entry: 0,
+            trytes: read_raw_image(bytes)?,
+        });
+    }
+
+    if bytes.len() < HEADER_BYTES {
+        return Err(ImageError::Truncated {
+            expected: HEADER_BYTES,
+            found: bytes.len(),
+        });
+    }
+    let version = bytes[4];
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let entry = i64::from_le_bytes(bytes[5..13].try_into().unwrap());
+    let tryte_count = u64::from_le_bytes(bytes[13..21].try_into().unwrap()) as usize;
+
+    let trytes = unpack_bct(&bytes[HEADER_BYTES..], tryte_count, HEADER_BYTES)?;
+    Ok(ProgramImage { entry, trytes })
+}
@@ -1,13 +1,21 @@
 // lib.rs - Core data types and math functions for the btern architecture.
 
+use std::cmp::Ordering;
 use std::fmt;
 use std::ops::Neg;
 
+pub mod dense;
+pub mod exe;
+pub mod image;
+pub mod object;
+pub mod trace;
+
 // --- Trit Module ---
 
 /// Represents a single balanced ternary digit {-1, 0, +1}.
 /// Using a C-style enum with explicit discriminants for clarity.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// The derived ordering follows the values: N < Z < P.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 #[repr(i8)]
 pub enum Trit {
     N = -1, // Negative
@@ -32,6 +40,25 @@ impl Trit {
         }
     }
 
+    /// Parses a Trit from its display character: '-', '0' or '+'.
+    pub fn from_char(c: char) -> Result<Self, &'static str> {
+        match c {
+            '-' => Ok(Trit::N),
+            '0' => Ok(Trit::Z),
+            '+' => Ok(Trit::P),
+            _ => Err("Invalid character for Trit; must be '-', '0', or '+'."),
+        }
+    }
+
+    /// Returns the consensus of two Trits: their common value if they agree, otherwise Z.
+    pub fn consensus(self, other: Self) -> Self {
+        if self == other {
+            self
+        } else {
+            Trit::Z
+        }
+    }
+
     /// Converts a Trit into its 2-bit Binary Coded Ternary (BCT) representation.
     /// -1 (N) -> 00
     ///  0 (Z) -> 01
@@ -87,6 +114,34 @@ pub type Word = [Trit; 27];
 /// A Tryte is 9 trits, the fundamental addressable unit of memory.
 pub type Tryte = [Trit; 9];
 
+/// Largest value representable in a Word: (3^27 - 1) / 2.
+pub const WORD_MAX: i64 = 3_812_798_742_493;
+
+/// Largest value representable in a Tryte: (3^9 - 1) / 2.
+pub const TRYTE_MAX: i64 = 9_841;
+
+/// Number of Trytes in a Word; every instruction occupies this many memory addresses.
+pub const TRYTES_PER_WORD: usize = 3;
+
+/// Number of general-purpose registers (R0-R26).
+pub const NUM_REGISTERS: usize = 27;
+
+/// Assembler temporary register index (R24, by software convention). basm's
+/// compare-and-branch pseudo-instructions clobber it.
+pub const REG_AT: usize = 24;
+
+/// Stack Pointer register index (R25, by software convention).
+pub const REG_SP: usize = 25;
+
+/// Link Register index (R26), written by CALL and read by RET.
+pub const REG_LR: usize = 26;
+
+/// Largest value representable in the 12-trit Imm/Offset field: (3^12 - 1) / 2.
+pub const IMM_MAX: i64 = 265_720;
+
+/// Smallest value representable in the 12-trit Imm/Offset field.
+pub const IMM_MIN: i64 = -IMM_MAX;
+
 // --- Instruction Set Definition ---
 
 /// Defines the instruction opcodes.
@@ -106,12 +161,193 @@ pub enum Opcode {
     CALL = 8,   // R26 = PC + 1; PC = PC + Offset (J-Type)
     RET = 9,    // PC = R26 (Reg)
     BRZ = 10,   // if (Rcond == 0) PC = PC + Offset (B-Type)
+    MUL = 11,   // Rd = Rs1 * Rs2 (3-Reg)
+    DIV = 12,   // Rd = Rs1 / Rs2, rounded to nearest; faults if Rs2 == 0 (3-Reg)
+    NEG = 13,   // Rd = -Rs1 (2-Reg)
+    ABS = 14,   // Rd = |Rs1| (2-Reg)
+    BRP = 15,   // if (Rcond > 0) PC = PC + Offset (B-Type)
+    BRN = 16,   // if (Rcond < 0) PC = PC + Offset (B-Type)
+    MIN = 17,   // Rd[i] = min(Rs1[i], Rs2[i]) (3-Reg, logical AND)
+    MAX = 18,   // Rd[i] = max(Rs1[i], Rs2[i]) (3-Reg, logical OR)
+    CONS = 19,  // Rd[i] = consensus(Rs1[i], Rs2[i]) (3-Reg)
+    INV = 20,   // Rd = -Rs1 (2-Reg, alias for NEG)
+    SHL = 21,   // Rd = Rs1 * 3^Imm (Reg-Imm)
+    SHR = 22,   // Rd = Rs1 / 3^Imm, truncated (= rounded) (Reg-Imm)
+    ROTL = 23,  // Rd = Rs1 rotated left by Imm trits (Reg-Imm)
+    ROTR = 24,  // Rd = Rs1 rotated right by Imm trits (Reg-Imm)
+    LDT = 25,   // Rd = Mem[Rs1 + Offset], one Tryte, sign-extended (I-Type)
+    STT = 26,   // Mem[Rs1 + Offset] = lower 9 trits of Rs2 (I-Type)
     // Placeholder for other instructions...
-    HALT = 63, // Arbitrary high value for termination
+    HALT = 63, // Stop; Rs1 holds the exit code (Reg)
+}
+
+/// Describes which Instruction fields an opcode uses, and therefore how its
+/// operands are written in assembly source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// No operands (e.g. `NOP`, `RET`, `HALT`).
+    None,
+    /// `Rd, Rs1, Rs2` (3-Reg).
+    ThreeReg,
+    /// `Rd, Rs1` (2-Reg).
+    TwoReg,
+    /// `Rd, Rs1, Imm` (Reg-Imm, and loads: Rd = Mem[Rs1 + Imm]).
+    RegImm,
+    /// `Rs2, Rs1, Imm` (stores: Mem[Rs1 + Imm] = Rs2).
+    Store,
+    /// `Offset` (J-Type, PC-relative).
+    Jump,
+    /// `Rs1, Offset` (B-Type, PC-relative).
+    Branch,
+    /// `[Rs1]` - optional register holding the exit code (`HALT`).
+    Halt,
+}
+
+impl Opcode {
+    /// Returns the assembly mnemonic for this opcode.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::NOP => "NOP",
+            Opcode::ADD => "ADD",
+            Opcode::ADDI => "ADDI",
+            Opcode::SUB => "SUB",
+            Opcode::SUBI => "SUBI",
+            Opcode::LDW => "LDW",
+            Opcode::STW => "STW",
+            Opcode::JMP => "JMP",
+            Opcode::CALL => "CALL",
+            Opcode::RET => "RET",
+            Opcode::BRZ => "BRZ",
+            Opcode::MUL => "MUL",
+            Opcode::DIV => "DIV",
+            Opcode::NEG => "NEG",
+            Opcode::ABS => "ABS",
+            Opcode::BRP => "BRP",
+            Opcode::BRN => "BRN",
+            Opcode::MIN => "MIN",
+            Opcode::MAX => "MAX",
+            Opcode::CONS => "CONS",
+            Opcode::INV => "INV",
+            Opcode::SHL => "SHL",
+            Opcode::SHR => "SHR",
+            Opcode::ROTL => "ROTL",
+            Opcode::ROTR => "ROTR",
+            Opcode::LDT => "LDT",
+            Opcode::STT => "STT",
+            Opcode::HALT => "HALT",
+        }
+    }
+
+    /// Looks up an opcode by its mnemonic (case-insensitive).
+    pub fn from_mnemonic(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "NOP" => Some(Opcode::NOP),
+            "ADD" => Some(Opcode::ADD),
+            "ADDI" => Some(Opcode::ADDI),
+            "SUB" => Some(Opcode::SUB),
+            "SUBI" => Some(Opcode::SUBI),
+            "LDW" => Some(Opcode::LDW),
+            "STW" => Some(Opcode::STW),
+            "JMP" => Some(Opcode::JMP),
+            "CALL" => Some(Opcode::CALL),
+            "RET" => Some(Opcode::RET),
+            "BRZ" => Some(Opcode::BRZ),
+            "MUL" => Some(Opcode::MUL),
+            "DIV" => Some(Opcode::DIV),
+            "NEG" => Some(Opcode::NEG),
+            "ABS" => Some(Opcode::ABS),
+            "BRP" => Some(Opcode::BRP),
+            "BRN" => Some(Opcode::BRN),
+            "MIN" => Some(Opcode::MIN),
+            "MAX" => Some(Opcode::MAX),
+            "CONS" => Some(Opcode::CONS),
+            "INV" => Some(Opcode::INV),
+            "SHL" => Some(Opcode::SHL),
+            "SHR" => Some(Opcode::SHR),
+            "ROTL" => Some(Opcode::ROTL),
+            "ROTR" => Some(Opcode::ROTR),
+            "LDT" => Some(Opcode::LDT),
+            "STT" => Some(Opcode::STT),
+            "HALT" => Some(Opcode::HALT),
+            _ => None,
+        }
+    }
+
+    /// Returns the operand format used by this opcode.
+    pub fn format(self) -> Format {
+        match self {
+            Opcode::NOP | Opcode::RET => Format::None,
+            Opcode::HALT => Format::Halt,
+            Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV => Format::ThreeReg,
+            Opcode::MIN | Opcode::MAX | Opcode::CONS => Format::ThreeReg,
+            Opcode::NEG | Opcode::ABS | Opcode::INV => Format::TwoReg,
+            Opcode::ADDI | Opcode::SUBI | Opcode::LDW | Opcode::LDT => Format::RegImm,
+            Opcode::SHL | Opcode::SHR | Opcode::ROTL | Opcode::ROTR => Format::RegImm,
+            Opcode::STW | Opcode::STT => Format::Store,
+            Opcode::JMP | Opcode::CALL => Format::Jump,
+            Opcode::BRZ | Opcode::BRP | Opcode::BRN => Format::Branch,
+        }
+    }
 }
 
+impl TryFrom<i64> for Opcode {
+    type Error = DecodeError;
+
+    /// Converts the integer value of a 6-trit opcode field into an Opcode.
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Opcode::NOP),
+            1 => Ok(Opcode::ADD),
+            2 => Ok(Opcode::ADDI),
+            3 => Ok(Opcode::SUB),
+            4 => Ok(Opcode::SUBI),
+            5 => Ok(Opcode::LDW),
+            6 => Ok(Opcode::STW),
+            7 => Ok(Opcode::JMP),
+            8 => Ok(Opcode::CALL),
+            9 => Ok(Opcode::RET),
+            10 => Ok(Opcode::BRZ),
+            11 => Ok(Opcode::MUL),
+            12 => Ok(Opcode::DIV),
+            13 => Ok(Opcode::NEG),
+            14 => Ok(Opcode::ABS),
+            15 => Ok(Opcode::BRP),
+            16 => Ok(Opcode::BRN),
+            17 => Ok(Opcode::MIN),
+            18 => Ok(Opcode::MAX),
+            19 => Ok(Opcode::CONS),
+            20 => Ok(Opcode::INV),
+            21 => Ok(Opcode::SHL),
+            22 => Ok(Opcode::SHR),
+            23 => Ok(Opcode::ROTL),
+            24 => Ok(Opcode::ROTR),
+            25 => Ok(Opcode::LDT),
+            26 => Ok(Opcode::STT),
+            63 => Ok(Opcode::HALT),
+            _ => Err(DecodeError::UnknownOpcode(value)),
+        }
+    }
+}
+
+/// Errors that can occur while decoding an instruction Word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The opcode field does not name a known instruction.
+    UnknownOpcode(i64),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(value) => write!(f, "Unknown opcode: {}", value),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 /// Represents a decoded instruction.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub rd: usize,      // Destination Register Index (0-26)
@@ -120,6 +356,48 @@ pub struct Instruction {
     pub imm: i64,       // Immediate/Offset value (12 trits, signed)
 }
 
+/// Formats the instruction in basm assembly syntax, e.g. `ADDI R1, R0, 5`.
+/// PC-relative offsets are printed as raw numbers, which basm accepts as-is.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.opcode.mnemonic();
+        match self.opcode.format() {
+            Format::None => write!(f, "{}", name),
+            Format::ThreeReg => write!(f, "{} R{}, R{}, R{}", name, self.rd, self.rs1, self.rs2),
+            Format::TwoReg => write!(f, "{} R{}, R{}", name, self.rd, self.rs1),
+            Format::RegImm => write!(f, "{} R{}, R{}, {}", name, self.rd, self.rs1, self.imm),
+            Format::Store => write!(f, "{} R{}, R{}, {}", name, self.rs2, self.rs1, self.imm),
+            Format::Jump => write!(f, "{} {}", name, self.imm),
+            Format::Branch => write!(f, "{} R{}, {}", name, self.rs1, self.imm),
+            Format::Halt if self.rs1 == 0 => write!(f, "{}", name),
+            Format::Halt => write!(f, "{} R{}", name, self.rs1),
+        }
+    }
+}
+
+impl Instruction {
+    /// Returns the registers whose values the instruction reads, in operand order.
+    /// `RET` reads the link register (R26); `HALT` reads its exit-code register.
+    pub fn source_registers(&self) -> Vec<usize> {
+        match self.opcode.format() {
+            Format::ThreeReg | Format::Store => vec![self.rs1, self.rs2],
+            Format::TwoReg | Format::RegImm | Format::Branch | Format::Halt => vec![self.rs1],
+            Format::None if self.opcode == Opcode::RET => vec![REG_LR],
+            Format::None | Format::Jump => Vec::new(),
+        }
+    }
+
+    /// Returns the register the instruction writes, if any: Rd, or the link
+    /// register (R26) for `CALL`. A write to R0 is reported even though it is discarded.
+    pub fn destination_register(&self) -> Option<usize> {
+        match self.opcode.format() {
+            Format::ThreeReg | Format::TwoReg | Format::RegImm => Some(self.rd),
+            Format::Jump if self.opcode == Opcode::CALL => Some(REG_LR),
+            _ => None,
+        }
+    }
+}
+
 impl Default for Instruction {
     fn default() -> Self {
         Instruction {
@@ -179,6 +457,44 @@ pub fn add_words(a: &Word, b: &Word) -> Word {
     result
 }
 
+/// Adds `addend` into `acc` trit by trit (both LSB first, `addend` no longer than `acc`)
+/// and returns the carry out of the most significant trit.
+fn add_into(acc: &mut [Trit], addend: &[Trit]) -> Trit {
+    let mut carry = Trit::Z;
+    for (i, trit) in acc.iter_mut().enumerate() {
+        let (sum, new_carry) = add_trits(*trit, addend.get(i).copied().unwrap_or(Trit::Z), carry);
+        *trit = sum;
+        carry = new_carry;
+    }
+    carry
+}
+
+/// Adds two Words, returning None if the sum does not fit in 27 trits.
+pub fn checked_add_words(a: &Word, b: &Word) -> Option<Word> {
+    let mut result = *a;
+    (add_into(&mut result, b) == Trit::Z).then_some(result)
+}
+
+/// Multiplies two Words, returning None if the product does not fit in 27 trits.
+/// The product is accumulated over 54 trits, so intermediate sums cannot wrap.
+pub fn checked_mul_words(a: &Word, b: &Word) -> Option<Word> {
+    let mut product = [Trit::Z; 54];
+    for (shift, &multiplier) in b.iter().enumerate() {
+        match multiplier {
+            Trit::Z => continue,
+            Trit::P => add_into(&mut product[shift..], a),
+            Trit::N => add_into(&mut product[shift..], &neg_word(a)),
+        };
+    }
+
+    if product[27..].iter().any(|&t| t != Trit::Z) {
+        return None;
+    }
+    let mut result = [Trit::Z; 27];
+    result.copy_from_slice(&product[..27]);
+    Some(result)
+}
+
 /// Performs trit-wise negation of a Word.
 pub fn neg_word(word: &Word) -> Word {
     let mut result = [Trit::Z; 27];
@@ -188,6 +504,134 @@ pub fn neg_word(word: &Word) -> Word {
     result
 }
 
+/// Applies a trit-level operation to each pair of corresponding trits of two Words.
+fn zip_words(a: &Word, b: &Word, op: impl Fn(Trit, Trit) -> Trit) -> Word {
+    let mut result = [Trit::Z; 27];
+    for i in 0..27 {
+        result[i] = op(a[i], b[i]);
+    }
+    result
+}
+
+/// Performs trit-wise minimum of two Words (ternary logical AND).
+pub fn min_words(a: &Word, b: &Word) -> Word {
+    zip_words(a, b, Trit::min)
+}
+
+/// Performs trit-wise maximum of two Words (ternary logical OR).
+pub fn max_words(a: &Word, b: &Word) -> Word {
+    zip_words(a, b, Trit::max)
+}
+
+/// Performs trit-wise consensus of two Words: each trit keeps its value where
+/// both inputs agree and becomes zero where they differ.
+pub fn consensus_words(a: &Word, b: &Word) -> Word {
+    zip_words(a, b, Trit::consensus)
+}
+
+/// Shifts a Word left by `count` trits (multiplies by 3^count).
+/// Trits shifted past the MSB are discarded; a count of 27 or more yields zero.
+pub fn shl_word(word: &Word, count: usize) -> Word {
+    let mut result = [Trit::Z; 27];
+    if count < 27 {
+        result[count..].copy_from_slice(&word[..27 - count]);
+    }
+    result
+}
+
+/// Shifts a Word right by `count` trits (divides by 3^count).
+/// In balanced ternary, dropping the low trits truncates and rounds to nearest
+/// at the same time, so no rounding correction is needed.
+pub fn shr_word(word: &Word, count: usize) -> Word {
+    let mut result = [Trit::Z; 27];
+    if count < 27 {
+        result[..27 - count].copy_from_slice(&word[count..]);
+    }
+    result
+}
+
+/// Rotates a Word left (toward the MSB) by `count` trits, modulo 27.
+pub fn rotl_word(word: &Word, count: usize) -> Word {
+    let mut result = *word;
+    result.rotate_right(count % 27); // Index 0 is the LSB, so "left" moves trits to higher indices.
+    result
+}
+
+/// Rotates a Word right (toward the LSB) by `count` trits, modulo 27.
+pub fn rotr_word(word: &Word, count: usize) -> Word {
+    let mut result = *word;
+    result.rotate_left(count % 27);
+    result
+}
+
+/// Returns the sign of a Word: the value of its most significant non-zero trit,
+/// or Trit::Z if every trit is zero.
+pub fn sign_word(word: &Word) -> Trit {
+    word.iter().rev().copied().find(|&t| t != Trit::Z).unwrap_or(Trit::Z)
+}
+
+/// Compares two Words by value. Balanced ternary needs no sign handling: the
+/// trits are compared from the MSB down, like the digits of unsigned numbers.
+pub fn compare_words(a: &Word, b: &Word) -> Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+/// Returns the absolute value of a Word.
+pub fn abs_word(word: &Word) -> Word {
+    if sign_word(word) == Trit::N {
+        neg_word(word)
+    } else {
+        *word
+    }
+}
+
+/// Performs balanced ternary multiplication of two Words using shift-and-add.
+/// Each non-zero trit of `b` adds (+1) or subtracts (-1) a shifted copy of `a`;
+/// no sign handling is needed. The product wraps to 27 trits on overflow.
+pub fn mul_words(a: &Word, b: &Word) -> Word {
+    let mut result = [Trit::Z; 27];
+
+    for (shift, &multiplier) in b.iter().enumerate() {
+        if multiplier == Trit::Z {
+            continue;
+        }
+
+        // Partial product: a * 3^shift, with trits shifted past the MSB discarded.
+        let mut partial = [Trit::Z; 27];
+        partial[shift..].copy_from_slice(&a[..27 - shift]);
+        if multiplier == Trit::N {
+            partial = neg_word(&partial);
+        }
+
+        result = add_words(&result, &partial);
+    }
+
+    result
+}
+
+/// Performs balanced ternary division of two Words.
+/// Returns (quotient, remainder) with a = quotient * b + remainder, or None if b is zero.
+/// The quotient is rounded to the nearest integer (balanced rounding, ties toward zero),
+/// so the remainder always satisfies |remainder| <= |b| / 2.
+pub fn div_words(a: &Word, b: &Word) -> Option<(Word, Word)> {
+    let dividend = word_to_i64(a);
+    let divisor = word_to_i64(b);
+    if divisor == 0 {
+        return None;
+    }
+
+    // Start from the truncated quotient, then step one unit toward the nearer integer.
+    let mut quotient = dividend / divisor;
+    let mut remainder = dividend % divisor;
+    if 2 * remainder.abs() > divisor.abs() {
+        let direction = dividend.signum() * divisor.signum();
+        quotient += direction;
+        remainder -= direction * divisor;
+    }
+
+    Some((i64_to_word(quotient), i64_to_word(remainder)))
+}
+
 /// Converts a slice of balanced trits into a signed i64 integer.
 /// The trits must be ordered from LSB (index 0) to MSB.
 pub fn trits_to_i64(trits: &[Trit]) -> i64 {
@@ -215,7 +659,8 @@ pub fn i64_to_word(mut value: i64) -> Word {
 
     while value != 0 && i < 27 {
         // The remainder when dividing by 3 will be 0, 1, or 2 (unbalanced ternary).
-        let rem = value % 3;
+        // rem_euclid keeps the remainder non-negative for negative values as well.
+        let rem = value.rem_euclid(3);
         
         // Convert unbalanced remainder (0, 1, 2) to balanced trit (-1, 0, 1)
         let trit_val = match rem {
@@ -236,6 +681,85 @@ pub fn i64_to_word(mut value: i64) -> Word {
     word
 }
 
+/// Parses a trit string written most significant trit first (e.g. "+0-" = 9 - 1 = 8).
+/// The returned trits are ordered from LSB (index 0) to MSB, like every other trit slice.
+pub fn trits_from_str(text: &str) -> Result<Vec<Trit>, &'static str> {
+    if text.is_empty() {
+        return Err("Empty trit string.");
+    }
+    text.chars().rev().map(Trit::from_char).collect()
+}
+
+/// Formats a trit slice (ordered LSB first) as a string written most significant
+/// trit first, e.g. "+0-" for 8. This is the inverse of trits_from_str.
+pub fn trits_to_string(trits: &[Trit]) -> String {
+    trits.iter().rev().map(|t| t.to_string()).collect()
+}
+
+/// Digits of heptavintimal (base 27) notation, indexed by digit value modulo 27:
+/// `0`-`9` and `A`-`D` are 0 to 13, and `E`-`Z` are -13 to -1. Each digit stands for
+/// exactly three trits, so a Tryte is three digits and a Word nine. Letters that are
+/// easily confused with digits (I, J, L, O, Q, S, U, W, Y) are not used.
+pub const HEPTAVINTIMAL_DIGITS: &str = "0123456789ABCDEFGHKMNPRTVXZ";
+
+/// Parses a heptavintimal string written most significant digit first (e.g. "1Z" = 27 - 1 = 26),
+/// ignoring case. The returned trits are ordered from LSB (index 0) to MSB, three per digit.
+pub fn trits_from_heptavintimal(text: &str) -> Result<Vec<Trit>, &'static str> {
+    if text.is_empty() {
+        return Err("Empty heptavintimal string.");
+    }
+
+    let mut trits = Vec::with_capacity(text.len() * 3);
+    for c in text.chars().rev() {
+        let index = HEPTAVINTIMAL_DIGITS
+            .find(c.to_ascii_uppercase())
+            .ok_or("Invalid heptavintimal digit.")? as i64;
+        let value = if index > 13 { index - 27 } else { index };
+        trits.extend(i64_to_trits_fixed_size(value, 3));
+    }
+    Ok(trits)
+}
+
+/// Formats a trit slice (ordered LSB first) as heptavintimal, most significant digit
+/// first, one digit per three trits (a partial group at the top is zero-padded).
+/// This is the inverse of trits_from_heptavintimal.
+pub fn trits_to_heptavintimal(trits: &[Trit]) -> String {
+    trits
+        .chunks(3)
+        .rev()
+        .map(|group| {
+            let index = trits_to_i64(group).rem_euclid(27) as usize;
+            HEPTAVINTIMAL_DIGITS.as_bytes()[index] as char
+        })
+        .collect()
+}
+
+/// Joins three Trytes, least significant first, into a Word (the inverse of word_to_trytes).
+pub fn trytes_to_word(trytes: &[Tryte]) -> Word {
+    let mut word = [Trit::Z; 27];
+    for (i, tryte) in trytes.iter().take(3).enumerate() {
+        word[i * 9..(i + 1) * 9].copy_from_slice(tryte);
+    }
+    word
+}
+
+/// Splits a Word into its three Trytes, least significant Tryte first.
+/// This is the order in which Words are laid out in memory.
+pub fn word_to_trytes(word: &Word) -> [Tryte; 3] {
+    let mut trytes = [[Trit::Z; 9]; 3];
+    for (i, tryte) in trytes.iter_mut().enumerate() {
+        tryte.copy_from_slice(&word[i * 9..(i + 1) * 9]);
+    }
+    trytes
+}
+
+/// Converts a signed i64 integer into a Tryte, keeping the lowest 9 trits.
+pub fn i64_to_tryte(value: i64) -> Tryte {
+    let mut tryte = [Trit::Z; 9];
+    tryte.copy_from_slice(&i64_to_word(value)[0..9]);
+    tryte
+}
+
 /// Converts a signed i64 integer into a balanced ternary Word of a specific size (e.g., 3 trits for register index).
 /// This is used for encoding small fields within a Word.
 fn i64_to_trits_fixed_size(mut value: i64, size: usize) -> Vec<Trit> {
@@ -243,7 +767,7 @@ fn i64_to_trits_fixed_size(mut value: i64, size: usize) -> Vec<Trit> {
     let mut i = 0;
 
     while value != 0 && i < size {
-        let rem = value % 3;
+        let rem = value.rem_euclid(3);
         
         let trit_val = match rem {
             0 => 0,
@@ -264,7 +788,20 @@ fn i64_to_trits_fixed_size(mut value: i64, size: usize) -> Vec<Trit> {
 
 /// Encodes an Instruction struct into a 27-trit Word.
 /// Format (LSB to MSB): [Imm/Offset: 12 | Rs2: 3 | Rs1: 3 | Rd: 3 | Opcode: 6]
+/// A 3-trit register field holds -13..13, so indices 14-26 are stored modulo 27
+/// (e.g. R26 is encoded as -1); decode_instruction maps negative fields back by adding 27.
+///
+/// Round trip: for any Instruction whose registers are 0-26 and whose imm lies in
+/// IMM_MIN..=IMM_MAX, `decode_instruction(&encode_instruction(&inst)) == Ok(inst)`.
+/// Out-of-range fields would be silently truncated, so they are rejected in debug builds.
 pub fn encode_instruction(inst: &Instruction) -> Word {
+    debug_assert!(
+        inst.rd < NUM_REGISTERS && inst.rs1 < NUM_REGISTERS && inst.rs2 < NUM_REGISTERS,
+        "register index out of range in {:?}",
+        inst
+    );
+    debug_assert!((IMM_MIN..=IMM_MAX).contains(&inst.imm), "immediate out of range in {:?}", inst);
+
     let mut word = [Trit::Z; 27];
     let mut current_idx = 0;
 
@@ -294,4 +831,106 @@ pub fn encode_instruction(inst: &Instruction) -> Word {
     // current_idx += 6; // Should equal 27 now
 
     word
-}
\ No newline at end of file
+}
+
+/// Decodes a 27-trit instruction Word into an Instruction struct.
+/// This is the inverse of encode_instruction, shared by every tool that reads machine code.
+///
+/// Round trip: every trit of the Word belongs to exactly one field, so for any Word
+/// that decodes successfully, `encode_instruction(&decode_instruction(&word)?) == word`.
+pub fn decode_instruction(word: &Word) -> Result<Instruction, DecodeError> {
+    // Opcode: 6 trits (21..26)
+    let opcode = Opcode::try_from(trits_to_i64(&word[21..27]))?;
+
+    // A 3-trit register field holds -13..13; registers 14-26 are stored modulo 27,
+    // so negative fields map back to the upper half of the register file.
+    let register_index = |trits: &[Trit]| trits_to_i64(trits).rem_euclid(NUM_REGISTERS as i64) as usize;
+
+    Ok(Instruction {
+        opcode,
+        rd: register_index(&word[18..21]),  // Rd: 3 trits (18..20)
+        rs1: register_index(&word[15..18]), // Rs1: 3 trits (15..17)
+        rs2: register_index(&word[12..15]), // Rs2: 3 trits (12..14)
+        imm: trits_to_i64(&word[0..12]),    // Imm/Offset: 12 trits (0..11)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Register indices at the edges of the 3-trit field: 13 is the largest stored
+    /// as is, 14 the first stored modulo 27, 26 the last.
+    const REGISTERS: [usize; 4] = [0, 13, 14, 26];
+
+    /// Immediates at the edges of the 12-trit field and around zero.
+    const IMMEDIATES: [i64; 5] = [IMM_MIN, -1, 0, 1, IMM_MAX];
+
+    /// Every opcode, found by trying each value that fits the tests' range.
+    fn all_opcodes() -> Vec<Opcode> {
+        (-40..=80).filter_map(|value| Opcode::try_from(value).ok()).collect()
+    }
+
+    #[test]
+    fn opcode_values_match_mnemonics() {
+        for value in -40..=80 {
+            match Opcode::try_from(value) {
+                Ok(opcode) => {
+                    assert_eq!(opcode as i64, value);
+                    assert_eq!(Opcode::from_mnemonic(opcode.mnemonic()), Some(opcode));
+                    assert_eq!(Opcode::from_mnemonic(&opcode.mnemonic().to_lowercase()), Some(opcode));
+                }
+                Err(error) => assert_eq!(error, DecodeError::UnknownOpcode(value)),
+            }
+        }
+        assert_eq!(all_opcodes().len(), 28);
+    }
+
+    #[test]
+    fn encode_then_decode_returns_the_instruction() {
+        for opcode in all_opcodes() {
+            for rd in REGISTERS {
+                for rs1 in REGISTERS {
+                    for rs2 in REGISTERS {
+                        for imm in IMMEDIATES {
+                            let inst = Instruction {
+                                opcode,
+                                rd,
+                                rs1,
+                                rs2,
+                                imm,
+                            };
+                            assert_eq!(decode_instruction(&encode_instruction(&inst)), Ok(inst));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_then_encode_returns_the_word() {
+        // Every opcode field value, with each other field at its extremes and around zero.
+        let registers = [-13, -1, 0, 1, 13];
+        for opcode in -364..=364 {
+            for rd in registers {
+                for rs1 in registers {
+                    for rs2 in registers {
+                        for imm in IMMEDIATES {
+                            let mut word = [Trit::Z; 27];
+                            word[0..12].copy_from_slice(&i64_to_trits_fixed_size(imm, 12));
+                            word[12..15].copy_from_slice(&i64_to_trits_fixed_size(rs2, 3));
+                            word[15..18].copy_from_slice(&i64_to_trits_fixed_size(rs1, 3));
+                            word[18..21].copy_from_slice(&i64_to_trits_fixed_size(rd, 3));
+                            word[21..27].copy_from_slice(&i64_to_trits_fixed_size(opcode, 6));
+                            match decode_instruction(&word) {
+                                Ok(inst) => assert_eq!(encode_instruction(&inst), word),
+                                Err(error) => assert_eq!(error, DecodeError::UnknownOpcode(opcode)),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+// object.rs - Relocatable object files and static archives, as written by
+// `basm -c` and combined into executables by bld.
+//
+// An object holds each section's contents as if the section started at address
+// 0. Every place that depends on where a symbol ends up is listed as a relocation,
+// which the linker patches once the final layout is known.
+//
+// Object file layout:
+//
+//   Offset  Size  Field
+//   0       4     Magic "BTOB"
+//   4       1     Format version (1)
+//   5       2     Section count, u16 little-endian
+//   7       4     Symbol count, u32 little-endian
+//   11      4     Relocation count, u32 little-endian
+//   15      ...   Sections, each:
+//                   name length (u8) and name (UTF-8)
+//                   size in Trytes, u64 little-endian
+//                   permissions, u8 (as in exe.rs)
+//                   initialized length in Trytes, u64 little-endian (0 for bss)
+//                   initialized contents, BCT-packed as in image.rs
+//   ...     ...   Symbols, each:
+//                   name length (u8) and name
+//                   section index, u16 little-endian (0xFFFF = undefined)
+//                   offset within the section, i64 little-endian
+//                   flags, u8 (1 = global)
+//   ...     ...   Relocations, each:
+//                   section index, u16 little-endian
+//                   offset of the patched Word or Tryte, u64 little-endian
+//                   kind, u8 (see RelocationKind)
+//                   symbol index, u32 little-endian
+//                   addend, i64 little-endian
+//   end-4   4     Checksum: 32-bit FNV-1a of every preceding byte, little-endian
+//
+// An archive is a named collection of object files:
+//
+//   0       4     Magic "BTAR"
+//   4       1     Format version (1)
+//   5       4     Member count, u32 little-endian
+//   9       ...   Members, each: name length (u8), name, byte length (u64), object file
+
+use crate::exe::{fnv1a, push_name, Permissions, Reader};
+use crate::image::{pack_bct, unpack_bct, ImageError, FORMAT_VERSION};
+use crate::{
+    i64_to_trits_fixed_size, i64_to_tryte, i64_to_word, trytes_to_word, word_to_trytes, Tryte, IMM_MAX, TRYTE_MAX,
+    WORD_MAX,
+};
+
+/// Magic number at the start of a relocatable object.
+pub const OBJECT_MAGIC: [u8; 4] = *b"BTOB";
+
+/// Magic number at the start of a static archive.
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"BTAR";
+
+/// Section index of a symbol that is referenced but not defined in the object.
+const UNDEFINED_SECTION: u16 = u16::MAX;
+
+/// Size of the fixed object header in bytes.
+const OBJECT_HEADER_BYTES: usize = 15;
+
+/// How a relocation's value is written into the section.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// The Imm field of an instruction, as an offset from the instruction's own address.
+    Rel12,
+    /// The Imm field of an instruction, as an absolute tryte address.
+    Abs12,
+    /// A whole Tryte (`.tryte label`).
+    Abs9,
+    /// A whole Word (`.word label`).
+    Abs27,
+}
+
+impl RelocationKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            RelocationKind::Rel12 => 0,
+            RelocationKind::Abs12 => 1,
+            RelocationKind::Abs9 => 2,
+            RelocationKind::Abs27 => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(RelocationKind::Rel12),
+            1 => Some(RelocationKind::Abs12),
+            2 => Some(RelocationKind::Abs9),
+            3 => Some(RelocationKind::Abs27),
+            _ => None,
+        }
+    }
+
+    /// Returns a short name for messages and map files.
+    pub fn name(self) -> &'static str {
+        match self {
+            RelocationKind::Rel12 => "REL12",
+            RelocationKind::Abs12 => "ABS12",
+            RelocationKind::Abs9 => "ABS9",
+            RelocationKind::Abs27 => "ABS27",
+        }
+    }
+
+    /// Returns the number of Trytes the relocation reads and writes.
+    pub fn width(self) -> usize {
+        match self {
+            RelocationKind::Abs9 => 1,
+            _ => 3,
+        }
+    }
+
+    /// Returns the largest magnitude the patched field can hold.
+    pub fn max_value(self) -> i64 {
+        match self {
+            RelocationKind::Rel12 | RelocationKind::Abs12 => IMM_MAX,
+            RelocationKind::Abs9 => TRYTE_MAX,
+            RelocationKind::Abs27 => WORD_MAX,
+        }
+    }
+
+    /// Writes `value` into `site`, which holds the `width()` Trytes being relocated.
+    /// Returns false, leaving `site` untouched, if the value does not fit.
+    pub fn patch(self, site: &mut [Tryte], value: i64) -> bool {
+        if !(-self.max_value()..=self.max_value()).contains(&value) {
+            return false;
+        }
+        match self {
+            RelocationKind::Rel12 | RelocationKind::Abs12 => {
+                // Only the 12-trit Imm field changes; the opcode and registers are kept.
+                let mut word = trytes_to_word(site);
+                word[0..12].copy_from_slice(&i64_to_trits_fixed_size(value, 12));
+                site.copy_from_slice(&word_to_trytes(&word));
+            }
+            RelocationKind::Abs9 => site[0] = i64_to_tryte(value),
+            RelocationKind::Abs27 => site.copy_from_slice(&word_to_trytes(&i64_to_word(value))),
+        }
+        true
+    }
+}
+
+/// A section of an object, addressed from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSection {
+    /// Section name, e.g. ".text".
+    pub name: String,
+    /// Size in Trytes. At least `data.len()`.
+    pub size: usize,
+    pub permissions: Permissions,
+    /// Initialized contents; the rest of the section is zero.
+    pub data: Vec<Tryte>,
+}
+
+/// A symbol defined or referenced by an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    /// Index of the defining section, or None if the symbol is defined elsewhere.
+    pub section: Option<usize>,
+    /// Offset within the defining section.
+    pub offset: i64,
+    /// Global symbols are visible to other objects; local ones only to relocations.
+    pub global: bool,
+}
+
+/// A place in a section to patch with a symbol's final address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Index of the section being patched.
+    pub section: usize,
+    /// Tryte offset of the patched Word or Tryte within the section.
+    pub offset: usize,
+    pub kind: RelocationKind,
+    /// Index of the referenced symbol.
+    pub symbol: usize,
+    /// Constant added to the symbol's address.
+    pub addend: i64,
+}
+
+/// A relocatable object file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub sections: Vec<ObjectSection>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Encodes an object file.
+pub fn write_object(object: &Object) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&OBJECT_MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&(object.sections.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(object.symbols.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(object.relocations.len() as u32).to_le_bytes());
+
+    for section in &object.sections {
+        push_name(&mut bytes, &section.name);
+        bytes.extend_from_slice(&(section.size as u64).to_le_bytes());
+        bytes.push(section.permissions.to_bits());
+        bytes.extend_from_slice(&(section.data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&pack_bct(&section.data));
+    }
+
+    for symbol in &object.symbols {
+        push_name(&mut bytes, &symbol.name);
+        let section = symbol.section.map_or(UNDEFINED_SECTION, |index| index as u16);
+        bytes.extend_from_slice(&section.to_le_bytes());
+        bytes.extend_from_slice(&symbol.offset.to_le_bytes());
+        bytes.push(symbol.global as u8);
+    }
+
+    for relocation in &object.relocations {
+        bytes.extend_from_slice(&(relocation.section as u16).to_le_bytes());
+        bytes.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+        bytes.push(relocation.kind.to_byte());
+        bytes.extend_from_slice(&(relocation.symbol as u32).to_le_bytes());
+        bytes.extend_from_slice(&relocation.addend.to_le_bytes());
+    }
+
+    let checksum = fnv1a(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Decodes an object file written by write_object, verifying its checksum and
+/// that every section, symbol and relocation reference is in range.
+pub fn read_object(bytes: &[u8]) -> Result<Object, ImageError> {
+    if bytes.len() < OBJECT_HEADER_BYTES + 4 {
+        return Err(ImageError::Truncated {
+            expected: OBJECT_HEADER_BYTES + 4,
+            found: bytes.len(),
+        });
+    }
+
+    let (body, trailer) = bytes.split_at(bytes.len() - 4);
+    let stored = u32::from_le_bytes(trailer.try_into().unwrap());
+    let computed = fnv1a(body);
+    if stored != computed {
+        return Err(ImageError::ChecksumMismatch { stored, computed });
+    }
+
+    let mut reader = Reader { bytes: body, pos: 4 };
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    let section_count = reader.u16()? as usize;
+    let symbol_count = reader.u32()? as usize;
+    let relocation_count = reader.u32()? as usize;
+
+    let mut object = Object::default();
+    for _ in 0..section_count {
+        let name = reader.name()?;
+        let size = reader.u64()? as usize;
+        let permissions = Permissions::from_bits(reader.u8()?);
+        let data_len = reader.u64()? as usize;
+        if data_len > size {
+            return Err(ImageError::InvalidObject(format!(
+                "section {} has {} initialized Trytes but a size of {}",
+                name, data_len, size
+            )));
+        }
+        let offset = reader.pos;
+        let data = unpack_bct(reader.take(data_len.saturating_mul(9).div_ceil(4))?, data_len, offset)?;
+        object.sections.push(ObjectSection {
+            name,
+            size,
+            permissions,
+            data,
+        });
+    }
+
+    for _ in 0..symbol_count {
+        let name = reader.name()?;
+        let section = match reader.u16()? {
+            UNDEFINED_SECTION => None,
+            index if (index as usize) < section_count => Some(index as usize),
+            index => {
+                return Err(ImageError::InvalidObject(format!(
+                    "symbol {} refers to missing section {}",
+                    name, index
+                )))
+            }
+        };
+        let offset = reader.i64()?;
+        let global = reader.u8()? & 1 != 0;
+        object.symbols.push(ObjectSymbol {
+            name,
+            section,
+            offset,
+            global,
+        });
+    }
+
+    for _ in 0..relocation_count {
+        let section = reader.u16()? as usize;
+        let offset = reader.u64()? as usize;
+        let kind_byte = reader.u8()?;
+        let symbol = reader.u32()? as usize;
+        let addend = reader.i64()?;
+
+        let kind = RelocationKind::from_byte(kind_byte)
+            .ok_or_else(|| ImageError::InvalidObject(format!("unknown relocation kind {}", kind_byte)))?;
+        let target = object
+            .sections
+            .get(section)
+            .ok_or_else(|| ImageError::InvalidObject(format!("relocation refers to missing section {}", section)))?;
+        if offset.saturating_add(kind.width()) > target.data.len() {
+            return Err(ImageError::InvalidObject(format!(
+                "relocation at {}+{} lies outside the section's contents",
+                target.name, offset
+            )));
+        }
+        if symbol >= object.symbols.len() {
+            return Err(ImageError::InvalidObject(format!(
+                "relocation refers to missing symbol {}",
+                symbol
+            )));
+        }
+        object.relocations.push(Relocation {
+            section,
+            offset,
+            kind,
+            symbol,
+            addend,
+        });
+    }
+
+    Ok(object)
+}
+
+/// An object file stored in an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    /// Name of the member, usually the object's file name.
+    pub name: String,
+    pub object: Object,
+}
+
+/// Encodes a static archive.
+pub fn write_archive(members: &[ArchiveMember]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&ARCHIVE_MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&(members.len() as u32).to_le_bytes());
+    for member in members {
+        let object = write_object(&member.object);
+        push_name(&mut bytes, &member.name);
+        bytes.extend_from_slice(&(object.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&object);
+    }
+    bytes
+}
+
+/// Decodes a static archive written by write_archive.
+pub fn read_archive(bytes: &[u8]) -> Result<Vec<ArchiveMember>, ImageError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(4)? != ARCHIVE_MAGIC {
+        return Err(ImageError::InvalidObject("not an archive".to_string()));
+    }
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+
+    let count = reader.u32()? as usize;
+    let mut members = Vec::new();
+    for _ in 0..count {
+        let name = reader.name()?;
+        let len = reader.u64()? as usize;
+        let object = read_object(reader.take(len)?)?;
+        members.push(ArchiveMember { name, object });
+    }
+    Ok(members)
+}
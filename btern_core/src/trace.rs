@@ -0,0 +1,605 @@
+// trace.rs - Execution traces, as written by `bemu --trace`.
+//
+// A trace is a JSON-lines file: one object per retired instruction, in order.
+// Values are plain integers (a Word is at most 3812798742493 in magnitude, well
+// within the exact range of a JSON number), so traces from different bemu
+// versions can be compared field by field:
+//
+//   {"step":1,"pc":0,
+//    "instruction":{"text":"ADDI R25, R0, 147","opcode":"ADDI","rd":25,"rs1":0,"rs2":0,"imm":147},
+//    "reads":[{"reg":0,"value":0}],
+//    "writes":[{"reg":25,"old":0,"new":147}],
+//    "memory":null}
+//
+// `step` counts from 1 and `pc` is the tryte address of the instruction. `reads`
+// lists the source registers with the values the instruction saw, `writes` the
+// destination register (never R0, whose writes are discarded) before and after.
+// `memory` is null, or the data access of a load or store:
+//
+//   {"kind":"write","address":144,"old":[0,0,0],"new":[5,0,0]}
+//
+// with the effective address and the value of each tryte accessed, least
+// significant tryte first, before and after the instruction (the same for a read).
+// An instruction that faults does not retire and has no record.
+//
+// parse_trace reads a trace back (for bdiff); it accepts exactly this subset of
+// JSON: objects, arrays, strings, integers and null.
+
+use crate::{Instruction, Opcode};
+
+/// A register value an instruction read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterRead {
+    pub register: usize,
+    pub value: i64,
+}
+
+/// A register an instruction wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub register: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+/// The data access of a load or store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryEffect {
+    /// True for a store.
+    pub write: bool,
+    /// Effective tryte address.
+    pub address: i64,
+    /// Tryte values before the instruction, least significant tryte first.
+    pub old: Vec<i64>,
+    /// Tryte values after the instruction.
+    pub new: Vec<i64>,
+}
+
+/// One retired instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// 1-based count of retired instructions.
+    pub step: u64,
+    /// Tryte address of the instruction.
+    pub pc: i64,
+    pub instruction: Instruction,
+    pub reads: Vec<RegisterRead>,
+    pub writes: Vec<RegisterWrite>,
+    pub memory: Option<MemoryEffect>,
+}
+
+impl TraceRecord {
+    /// Formats the record as one line of JSON, without the newline.
+    pub fn to_json(&self) -> String {
+        let inst = &self.instruction;
+        let instruction = format!(
+            "{{\"text\":{},\"opcode\":{},\"rd\":{},\"rs1\":{},\"rs2\":{},\"imm\":{}}}",
+            json_string(&inst.to_string()),
+            json_string(inst.opcode.mnemonic()),
+            inst.rd,
+            inst.rs1,
+            inst.rs2,
+            inst.imm
+        );
+        let reads: Vec<String> = self
+            .reads
+            .iter()
+            .map(|r| format!("{{\"reg\":{},\"value\":{}}}", r.register, r.value))
+            .collect();
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .map(|w| format!("{{\"reg\":{},\"old\":{},\"new\":{}}}", w.register, w.old, w.new))
+            .collect();
+        let memory = match &self.memory {
+            None => "null".to_string(),
+            Some(m) => format!(
+                "{{\"kind\":\"{}\",\"address\":{},\"old\":[{}],\"new\":[{}]}}",
+                if m.write { "write" } else { "read" },
+                m.address,
+                join(&m.old),
+                join(&m.new)
+            ),
+        };
+        format!(
+            "{{\"step\":{},\"pc\":{},\"instruction\":{},\"reads\":[{}],\"writes\":[{}],\"memory\":{}}}",
+            self.step,
+            self.pc,
+            instruction,
+            reads.join(","),
+            writes.join(","),
+            memory
+        )
+    }
+
+    /// Parses one line written by to_json.
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            bytes: line.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != line.len() {
+            return Err(format!("unexpected text after the record at column {}", parser.pos + 1));
+        }
+
+        let record = value.object()?;
+        let inst = field(record, "instruction")?.object()?;
+        let opcode = field(inst, "opcode")?.string()?;
+        let instruction = Instruction {
+            opcode: Opcode::from_mnemonic(opcode).ok_or_else(|| format!("unknown opcode '{}'", opcode))?,
+            rd: register(field(inst, "rd")?)?,
+            rs1: register(field(inst, "rs1")?)?,
+            rs2: register(field(inst, "rs2")?)?,
+            imm: field(inst, "imm")?.integer()?,
+        };
+
+        let reads = field(record, "reads")?
+            .array()?
+            .iter()
+            .map(|read| {
+                let read = read.object()?;
+                Ok(RegisterRead {
+                    register: register(field(read, "reg")?)?,
+                    value: field(read, "value")?.integer()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let writes = field(record, "writes")?
+            .array()?
+            .iter()
+            .map(|write| {
+                let write = write.object()?;
+                Ok(RegisterWrite {
+                    register: register(field(write, "reg")?)?,
+                    old: field(write, "old")?.integer()?,
+                    new: field(write, "new")?.integer()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let memory = match field(record, "memory")? {
+            Json::Null => None,
+            memory => {
+                let memory = memory.object()?;
+                let trytes = |name: &str| -> Result<Vec<i64>, String> {
+                    field(memory, name)?.array()?.iter().map(Json::integer).collect()
+                };
+                Some(MemoryEffect {
+                    write: match field(memory, "kind")?.string()? {
+                        "write" => true,
+                        "read" => false,
+                        kind => return Err(format!("unknown memory access kind '{}'", kind)),
+                    },
+                    address: field(memory, "address")?.integer()?,
+                    old: trytes("old")?,
+                    new: trytes("new")?,
+                })
+            }
+        };
+
+        let step = field(record, "step")?.integer()?;
+        Ok(TraceRecord {
+            step: u64::try_from(step).map_err(|_| format!("invalid step number {}", step))?,
+            pc: field(record, "pc")?.integer()?,
+            instruction,
+            reads,
+            writes,
+            memory,
+        })
+    }
+}
+
+/// Parses a JSON-lines trace. Blank lines are skipped; errors name the line.
+pub fn parse_trace(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| TraceRecord::from_json(line).map_err(|e| format!("line {}: {}", index + 1, e)))
+        .collect()
+}
+
+/// A parsed JSON value.
+enum Json {
+    Null,
+    Integer(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Integer(_) => "an integer",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+
+    fn object(&self) -> Result<&[(String, Json)], String> {
+        match self {
+            Json::Object(fields) => Ok(fields),
+            other => Err(format!("expected an object, found {}", other.kind())),
+        }
+    }
+
+    fn array(&self) -> Result<&[Json], String> {
+        match self {
+            Json::Array(items) => Ok(items),
+            other => Err(format!("expected an array, found {}", other.kind())),
+        }
+    }
+
+    fn integer(&self) -> Result<i64, String> {
+        match self {
+            Json::Integer(value) => Ok(*value),
+            other => Err(format!("expected an integer, found {}", other.kind())),
+        }
+    }
+
+    fn string(&self) -> Result<&str, String> {
+        match self {
+            Json::String(text) => Ok(text),
+            other => Err(format!("expected a string, found {}", other.kind())),
+        }
+    }
+}
+
+fn field<'a>(object: &'a [(String, Json)], name: &str) -> Result<&'a Json, String> {
+    object
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| format!("missing field '{}'", name))
+}
+
+/// Reads a register number, which must be 0-26.
+fn register(value: &Json) -> Result<usize, String> {
+    match value.integer()? {
+        n @ 0..=26 => Ok(n as usize),
+        n => Err(format!("invalid register number {}", n)),
+    }
+}
+
+/// A recursive-descent parser over one line of JSON.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.bytes.get(self.pos) {
+            Some(&b) => format!("expected {} at column {}, found '{}'", expected, self.pos + 1, b as char),
+            None => format!("expected {} at column {}, found the end of the line", expected, self.pos + 1),
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("'{}'", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.integer(),
+            _ if self.bytes[self.pos..].starts_with(b"null") => {
+                self.pos += 4;
+                Ok(Json::Null)
+            }
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("a field name"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&b) = self.bytes.get(self.pos) else {
+                return Err(self.error("the end of the string"));
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.bytes.get(self.pos).copied();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let hex = self.bytes.get(self.pos..self.pos + 4).and_then(|h| std::str::from_utf8(h).ok());
+                            let code = hex.and_then(|h| u32::from_str_radix(h, 16).ok());
+                            self.pos += 4;
+                            code.and_then(char::from_u32).ok_or("invalid \\u escape in a string")?
+                        }
+                        _ => return Err("invalid escape in a string".to_string()),
+                    };
+                    let mut buffer = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                _ => bytes.push(b),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "invalid UTF-8 in a string".to_string())
+    }
+
+    fn integer(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        if matches!(self.bytes.get(self.pos), Some(b'.' | b'e' | b'E')) {
+            return Err(format!("expected an integer at column {}", start + 1));
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+        text.parse()
+            .map(Json::Integer)
+            .map_err(|_| format!("invalid integer '{}' at column {}", text, start + 1))
+    }
+}
+
+fn join(values: &[i64]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Quotes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: Opcode, rd: usize, rs1: usize, rs2: usize, imm: i64) -> Instruction {
+        Instruction {
+            opcode,
+            rd,
+            rs1,
+            rs2,
+            imm,
+        }
+    }
+
+    fn records() -> Vec<TraceRecord> {
+        vec![
+            TraceRecord {
+                step: 1,
+                pc: 0,
+                instruction: instruction(Opcode::ADDI, 25, 0, 0, -147),
+                reads: vec![RegisterRead { register: 0, value: 0 }],
+                writes: vec![RegisterWrite {
+                    register: 25,
+                    old: 0,
+                    new: -147,
+                }],
+                memory: None,
+            },
+            TraceRecord {
+                step: 2,
+                pc: 3,
+                instruction: instruction(Opcode::STW, 0, 25, 26, 3),
+                reads: vec![
+                    RegisterRead {
+                        register: 25,
+                        value: 120,
+                    },
+                    RegisterRead {
+                        register: 26,
+                        value: -3812798742493,
+                    },
+                ],
+                writes: Vec::new(),
+                memory: Some(MemoryEffect {
+                    write: true,
+                    address: 123,
+                    old: vec![0, 0, 0],
+                    new: vec![-9841, 9841, -1],
+                }),
+            },
+            TraceRecord {
+                step: 3,
+                pc: 6,
+                instruction: instruction(Opcode::LDT, 14, 25, 0, 4),
+                reads: vec![RegisterRead {
+                    register: 25,
+                    value: 120,
+                }],
+                writes: vec![RegisterWrite {
+                    register: 14,
+                    old: 5,
+                    new: 9841,
+                }],
+                memory: Some(MemoryEffect {
+                    write: false,
+                    address: 124,
+                    old: vec![9841],
+                    new: vec![9841],
+                }),
+            },
+            TraceRecord {
+                step: 4,
+                pc: 9,
+                instruction: instruction(Opcode::HALT, 0, 2, 0, 0),
+                reads: vec![RegisterRead { register: 2, value: 15 }],
+                writes: Vec::new(),
+                memory: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn records_round_trip_through_json() {
+        for record in records() {
+            let line = record.to_json();
+            assert!(!line.contains('\n'));
+            assert_eq!(TraceRecord::from_json(&line), Ok(record), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_trace_reads_every_line_and_skips_blank_ones() {
+        let records = records();
+        let mut text: String = records.iter().map(|r| format!("{}\n", r.to_json())).collect();
+        text.insert(0, '\n');
+        text.push_str("  \n");
+        assert_eq!(parse_trace(&text), Ok(records));
+        assert_eq!(parse_trace(""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn whitespace_between_tokens_is_accepted() {
+        let line = " { \"step\" : 7 , \"pc\" : 21 ,\t\"instruction\" : { \"text\" : \"RET\" , \"opcode\" : \"RET\" ,
+            \"rd\" : 0 , \"rs1\" : 26 , \"rs2\" : 0 , \"imm\" : 0 } , \"reads\" : [ { \"reg\" : 26 , \"value\" : 9 } ] ,
+            \"writes\" : [ ] , \"memory\" : null } ";
+        let record = TraceRecord::from_json(&line.replace('\n', " ")).unwrap();
+        assert_eq!(record.step, 7);
+        assert_eq!(record.instruction, instruction(Opcode::RET, 0, 26, 0, 0));
+        assert_eq!(record.reads, vec![RegisterRead { register: 26, value: 9 }]);
+    }
+
+    #[test]
+    fn strings_are_escaped_and_unescaped() {
+        let text = "quote \" backslash \\ tab \t newline \n bell \u{7} é";
+        let quoted = json_string(text);
+        assert_eq!(
+            quoted,
+            "\"quote \\\" backslash \\\\ tab \\u0009 newline \\u000a bell \\u0007 é\""
+        );
+
+        let parse = |json: &str| {
+            let mut parser = Parser {
+                bytes: json.as_bytes(),
+                pos: 0,
+            };
+            parser.value().and_then(|value| value.string().map(str::to_string))
+        };
+        assert_eq!(parse(&quoted).as_deref(), Ok(text));
+        assert_eq!(parse(r#""\/\b\f\n\r\tAé""#).as_deref(), Ok("/\u{8}\u{c}\n\r\tAé"));
+        assert!(parse(r#""\x""#).is_err());
+        assert!(parse(r#""\u00""#).is_err());
+        assert!(parse(r#""\ud800""#).is_err());
+        assert!(parse(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected_with_their_line_number() {
+        let good = records()[0].to_json();
+        let poke = "\"memory\":{\"kind\":\"poke\",\"address\":0,\"old\":[],\"new\":[]}";
+        let cases = [
+            (good.replace("\"pc\":0", "\"pc\":1.5"), "expected an integer"),
+            (
+                good.replace("\"pc\":0", "\"pc\":\"0\""),
+                "expected an integer, found a string",
+            ),
+            (good.replace("\"pc\":0,", ""), "missing field 'pc'"),
+            (good.replace("ADDI\",\"rd\"", "FOO\",\"rd\""), "unknown opcode 'FOO'"),
+            (good.replace("\"rd\":25", "\"rd\":27"), "invalid register number 27"),
+            (good.replace("\"step\":1", "\"step\":-1"), "invalid step number -1"),
+            (
+                good.replace("\"memory\":null", poke),
+                "unknown memory access kind 'poke'",
+            ),
+            (
+                good.replace("\"step\":1", "\"step\":99999999999999999999"),
+                "invalid integer",
+            ),
+            (format!("{} x", good), "unexpected text after the record"),
+            (good[..good.len() - 1].to_string(), "expected ',' or '}'"),
+            ("[1,2".to_string(), "expected ',' or ']'"),
+            ("true".to_string(), "expected a value at column 1"),
+        ];
+        for (line, message) in cases {
+            let text = format!("{}\n\n{}\n", good, line);
+            let error = parse_trace(&text).unwrap_err();
+            assert!(error.starts_with("line 3: "), "{}", error);
+            assert!(error.contains(message), "{} (expected '{}')", error, message);
+        }
+    }
+}